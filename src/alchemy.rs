@@ -1,48 +1,272 @@
-use gradient_descent;
-use nalgebra::{MatrixN, Dynamic, VectorN};
+use dual;
+use gradient_descent::{Options, StopReason};
+use exact;
+use joint::JointTable;
+use nalgebra::DVector;
+use presolve::Domains;
+use propagation;
+use rayon::prelude::*;
+use sampler;
+use scaling;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use solver::{EntropyConstraint, EntropySubProblem, VarAndValue};
 use std::fmt::{Display, Formatter};
-use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::cmp;
+use std::f64;
 use std::fmt;
 
-type DynMatrix = MatrixN<f64, Dynamic>;
-type DynVector = VectorN<f64, Dynamic>;
+type DynVector = DVector<f64>;
 
 #[derive(Debug, Clone)]
-enum VariableType {
-    BaseVariable{ var1: VarAndValue, var2: VarAndValue, lagrangians: Vec<usize>, neg_lags: Vec<usize> },
+pub enum VariableType {
+    // Count is how many cells of the full joint table this one stands for, all equally likely.
+    BaseVariable{ var1: VarAndValue, var2: VarAndValue, count: f64, lagrangians: Vec<usize>, neg_lags: Vec<usize> },
     Lagrangian(Vec<usize>),
-    EquivalentSums(Vec<usize>, Vec<usize>)
+    EquivalentSums(Vec<usize>, Vec<usize>),
+    // The sum hits the target, or with at_least reaches it.
+    CountLagrangian{ sum: Vec<usize>, target: f64, at_least: bool }
 }
 
-use self::VariableType::{BaseVariable, Lagrangian, EquivalentSums};
+use self::VariableType::{BaseVariable, Lagrangian, EquivalentSums, CountLagrangian};
+
+// How each component is solved. Newton and Scaling find the same maximum, so one can check the other,
+// and Exact gives the true marginals the maximum entropy ones approximate. Sample and Propagation
+// estimate those for components too large to enumerate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Method {
+    // Newton on the dual. Few iterations, but each one is a linear solve.
+    Newton,
+    // Dual coordinate ascent. Many cheap sweeps, for problems too large for Newton.
+    Scaling,
+    // Enumerates every consistent assignment. Only feasible for small components.
+    Exact,
+    // Metropolis sampling of consistent assignments, with max_iter sweeps per chain.
+    Sample,
+    // Loopy belief propagation, with a factor for each constraint.
+    Propagation
+}
+
+impl Method {
+    pub fn default_options(&self) -> Options {
+        return match *self {
+            Method::Newton | Method::Exact => Options::default(),
+            Method::Scaling => Options{ max_iter: scaling::MAX_SWEEPS, ..Options::default() },
+            Method::Sample => Options{ max_iter: sampler::SWEEPS, ..Options::default() },
+            Method::Propagation => Options{ max_iter: propagation::MAX_ITERATIONS, ..Options::default() }
+        };
+    }
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Method, String> {
+        return match text {
+            "newton" => Ok(Method::Newton),
+            "scaling" | "ipf" => Ok(Method::Scaling),
+            "exact" => Ok(Method::Exact),
+            "sample" => Ok(Method::Sample),
+            "propagation" | "bp" => Ok(Method::Propagation),
+            other => Err(format!("unknown method {}, expected newton, scaling, exact, sample or propagation", other))
+        };
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        return match *self {
+            Method::Newton => write!(f, "newton"),
+            Method::Scaling => write!(f, "scaling"),
+            Method::Exact => write!(f, "exact"),
+            Method::Sample => write!(f, "sample"),
+            Method::Propagation => write!(f, "propagation")
+        };
+    }
+}
 
 pub struct OptimizationResult {
     distribution: HashMap<VarAndValue, f64>,
+    // Standard errors of the distribution. Empty unless it was estimated by sampling.
+    errors: HashMap<VarAndValue, f64>,
+    // The marginal of a variable as read from its joint with another, keyed by the other variable.
+    // Empty unless the method solves joints. The distribution is their mean.
+    joint_marginals: HashMap<(usize, VarAndValue), f64>,
+    // The solved joints, keyed by their variables in increasing order. Empty unless the method solves joints.
+    joints: HashMap<(usize, usize), JointTable>,
+    // The Lagrange multipliers, by what they constrain, so a problem with more constraints can start from them.
+    duals: HashMap<DualKey, f64>,
+    // What was solved and how, so it can be solved again with more constraints.
+    problem: Option<EntropyOptimizer>,
+    method: Method,
+    options: Options,
+    // The variables of components that stopped before their constraints held to the tolerance.
+    unconverged: BTreeSet<usize>,
     varc: usize,
     k: usize
 }
 
+// What a Lagrange multiplier constrains, in terms that outlast the variable layout of one solve.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DualKey {
+    // The joint of the two variables sums to one.
+    SumToOne(usize, usize),
+    // The first joint agrees with the second on the marginal of the given variable and value.
+    Equivalent(VarAndValue, usize, usize),
+    // An ExactlyOne or AtLeast constraint.
+    Constraint(EntropyConstraint)
+}
+
+impl DualKey {
+    // One of the variables the multiplier is about. They are all in the same component.
+    fn var(&self) -> usize {
+        return match *self {
+            DualKey::SumToOne(first, _) => first,
+            DualKey::Equivalent(given, _, _) => given.var,
+            DualKey::Constraint(ref contra) => contra.variables()[0]
+        };
+    }
+
+    // The same keys in the original problem. A lumped value gives one for each value it stands for.
+    fn originals(&self, sub: &EntropySubProblem) -> Vec<DualKey> {
+        let var = |var: usize| sub.var_map[var];
+        return match *self {
+            DualKey::SumToOne(first, second) => {
+                vec![DualKey::SumToOne(cmp::min(var(first), var(second)), cmp::max(var(first), var(second)))]
+            },
+            DualKey::Equivalent(given, first, second) => sub.k_map[given.value].iter()
+                .map(|&value| DualKey::Equivalent(VarAndValue{ var: var(given.var), value }, var(first), var(second)))
+                .collect(),
+            // Constraints only name values that are not lumped.
+            DualKey::Constraint(ref contra) => vec![DualKey::Constraint(contra.remap(var, |value| sub.k_map[value][0]))]
+        };
+    }
+}
+
 #[derive(PartialEq, Eq, Hash)]
 struct PartialLagrangian {
     given: VarAndValue,
     free: usize
 }
 
+#[derive(Clone, Serialize)]
 pub struct EntropyOptimizer {
     pub varc: usize,
     pub k: usize,
+    #[serde(serialize_with = "serialize_sorted")]
     pub contras: HashSet<EntropyConstraint>
 }
 
+// Sets have no order, so they are sorted to keep the JSON the same from run to run.
+fn serialize_sorted<S: Serializer>(contras: &HashSet<EntropyConstraint>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&EntropyConstraint> = contras.iter().collect();
+    sorted.sort();
+    return sorted.serialize(serializer);
+}
+
+#[derive(Deserialize)]
+struct ProblemSchema {
+    varc: usize,
+    k: usize,
+    contras: Vec<EntropyConstraint>
+}
+
+// Constraints read from JSON are checked, since the solvers index with them unchecked.
+impl<'de> Deserialize<'de> for EntropyOptimizer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<EntropyOptimizer, D::Error> {
+        let schema = try!(ProblemSchema::deserialize(deserializer));
+        return EntropyOptimizer::new(schema.varc, schema.k, schema.contras.into_iter().collect()).map_err(de::Error::custom);
+    }
+}
+
+// JSON keys must be strings, so the distribution is written as a list ordered by variable then value.
+#[derive(Serialize, Deserialize)]
+struct ResultSchema {
+    varc: usize,
+    k: usize,
+    converged: bool,
+    entropy: f64,
+    distribution: Vec<ProbabilitySchema>
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProbabilitySchema {
+    var: usize,
+    value: usize,
+    probability: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<f64>
+}
+
+impl Serialize for OptimizationResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut distribution: Vec<ProbabilitySchema> = self.distribution.iter()
+            .map(|(varval, &probability)| ProbabilitySchema{
+                var: varval.var,
+                value: varval.value,
+                probability,
+                error: self.errors.get(varval).cloned()
+            })
+            .collect();
+        distribution.sort_by_key(|prob| (prob.var, prob.value));
+        let schema = ResultSchema{
+            varc: self.varc,
+            k: self.k,
+            converged: self.converged(),
+            entropy: self.entropy(),
+            distribution
+        };
+        return schema.serialize(serializer);
+    }
+}
+
+// The entropy is recomputed from the distribution rather than trusted.
+impl<'de> Deserialize<'de> for OptimizationResult {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<OptimizationResult, D::Error> {
+        let schema = try!(ResultSchema::deserialize(deserializer));
+        let mut distribution: HashMap<VarAndValue, f64> = HashMap::new();
+        let mut errors: HashMap<VarAndValue, f64> = HashMap::new();
+        for prob in schema.distribution {
+            if prob.var >= schema.varc || prob.value >= schema.k {
+                return Err(de::Error::custom(format!("Pr[{}={}] is outside {} variables and {} values.", prob.var, prob.value, schema.varc, schema.k)));
+            }
+            let varval = VarAndValue{ var: prob.var, value: prob.value };
+            distribution.insert(varval, prob.probability);
+            if let Some(error) = prob.error {
+                errors.insert(varval, error);
+            }
+        }
+        return Ok(OptimizationResult{
+            distribution,
+            errors,
+            joint_marginals: HashMap::new(),
+            joints: HashMap::new(),
+            duals: HashMap::new(),
+            problem: None,
+            method: Method::Newton,
+            options: Options::default(),
+            // JSON only says whether everything converged.
+            unconverged: if schema.converged { BTreeSet::new() } else { (0..schema.varc).collect() },
+            varc: schema.varc,
+            k: schema.k
+        });
+    }
+}
+
 impl Display for OptimizationResult {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         try!(write!(f, "{{\n"));
         for n in 0..self.varc {
             for k in 0..self.k {
-                try!(write!(f, "\tPr[{}={}] = {}\n", n, k, self.var_prob(n, k)));
+                let varval = VarAndValue{ var: n, value: k };
+                match self.errors.get(&varval) {
+                    Some(error) => try!(write!(f, "\tPr[{}={}] = {} ± {}\n", n, k, self.var_prob(n, k), error)),
+                    None => try!(write!(f, "\tPr[{}={}] = {}\n", n, k, self.var_prob(n, k)))
+                }
             }
         }
-        write!(f, "}}\n")
+        writeln!(f, "}}")
     }
 }
 
@@ -53,7 +277,12 @@ impl OptimizationResult {
             .filter(|&&val| val > 0.0)
             .map(|val| val * val.log2())
             .sum();
-        return -1.0 * summ;
+        return -summ;
+    }
+
+    // False if any component stopped before its constraints held to the tolerance.
+    pub fn converged(&self) -> bool {
+        return self.unconverged.is_empty();
     }
 
     pub fn var_prob(&self, var: usize, value: usize) -> f64 {
@@ -61,27 +290,279 @@ impl OptimizationResult {
         let zero = 0.0;
         return *self.distribution.get(&varval).unwrap_or(&zero);
     }
+
+    // The marginal of var as its joint with other sees it, or None if the two share no joint.
+    pub fn joint_marginal(&self, var: usize, other: usize, value: usize) -> Option<f64> {
+        return self.joint_marginals.get(&(other, VarAndValue{ var, value })).cloned();
+    }
+
+    // The variables var shares a joint with.
+    pub fn partners(&self, var: usize) -> Vec<usize> {
+        // A joint has an entry for every value, so looking at value 0 finds each once.
+        let partners: BTreeSet<usize> = self.joint_marginals.keys()
+            .filter(|&&(_, varval)| varval.var == var && varval.value == 0)
+            .map(|&(other, _)| other)
+            .collect();
+        return partners.into_iter().collect();
+    }
+
+    // The largest gap between two joints' marginals for var, zero unless it is in several joints.
+    pub fn disagreement(&self, var: usize) -> f64 {
+        let partners = self.partners(var);
+        return (0..self.k)
+            .map(|value| {
+                let seen: Vec<f64> = partners.iter()
+                    .filter_map(|&other| self.joint_marginal(var, other, value))
+                    .collect();
+                let low = seen.iter().cloned().fold(f64::INFINITY, f64::min);
+                let high = seen.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                (high - low).max(0.0)
+            })
+            .fold(0.0, f64::max);
+    }
+
+    // The largest disagreement over every variable, in one pass over the joints.
+    pub fn max_disagreement(&self) -> f64 {
+        let mut ranges: HashMap<VarAndValue, (f64, f64)> = HashMap::new();
+        for (&(_, varval), &probability) in self.joint_marginals.iter() {
+            let range = ranges.entry(varval).or_insert((probability, probability));
+            range.0 = range.0.min(probability);
+            range.1 = range.1.max(probability);
+        }
+        return ranges.values().map(|&(low, high)| high - low).fold(0.0, f64::max);
+    }
+
+    // The pairs of variables with a solved joint, each in increasing order.
+    pub fn joint_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<(usize, usize)> = self.joints.keys().cloned().collect();
+        pairs.sort();
+        return pairs;
+    }
+
+    // Pr[var1 = value1, var2 = value2], or None if the pair has no solved joint.
+    pub fn joint_prob(&self, var1: usize, value1: usize, var2: usize, value2: usize) -> Option<f64> {
+        if var1 > var2 {
+            return self.joint_prob(var2, value2, var1, value1);
+        }
+        return self.joints.get(&(var1, var2)).map(|joint| joint.prob(value1, value2));
+    }
+
+    // In bits, how much learning var1 tells about var2, or None if the pair has no solved joint.
+    pub fn mutual_information(&self, var1: usize, var2: usize) -> Option<f64> {
+        let pair = (cmp::min(var1, var2), cmp::max(var1, var2));
+        return self.joints.get(&pair).map(|joint| joint.mutual_information());
+    }
+
+    // The result of adding the observation to the problem this solved, by the same method and starting
+    // from this solution. Results read back from JSON do not know their problem and cannot be conditioned.
+    pub fn condition(&self, observation: &[EntropyConstraint]) -> Result<OptimizationResult, String> {
+        let problem = try!(self.problem.as_ref().ok_or("The result does not record the problem it solved.".to_string()));
+        let mut conditioned = problem.clone();
+        for contra in observation.iter() {
+            conditioned.contras.insert(contra.clone());
+        }
+        return conditioned.solve(self.method, &self.options, Some(self));
+    }
+
+    // Copies everything known about the variables into another result's parts.
+    fn copy_vars(
+        &self,
+        vars: &HashSet<usize>,
+        distribution: &mut HashMap<VarAndValue, f64>,
+        errors: &mut HashMap<VarAndValue, f64>,
+        joint_marginals: &mut HashMap<(usize, VarAndValue), f64>,
+        joints: &mut HashMap<(usize, usize), JointTable>,
+        duals: &mut HashMap<DualKey, f64>)
+    {
+        distribution.extend(self.distribution.iter().filter(|&(varval, _)| vars.contains(&varval.var)));
+        errors.extend(self.errors.iter().filter(|&(varval, _)| vars.contains(&varval.var)));
+        joint_marginals.extend(self.joint_marginals.iter().filter(|&(&(other, _), _)| vars.contains(&other)));
+        joints.extend(self.joints.iter()
+            .filter(|&(&(first, _), _)| vars.contains(&first))
+            .map(|(&pair, joint)| (pair, joint.clone())));
+        duals.extend(self.duals.iter()
+            .filter(|&(key, _)| vars.contains(&key.var()))
+            .map(|(key, &value)| (key.clone(), value)));
+    }
+
+    // The standard error of var_prob, or None when it was not estimated by sampling.
+    pub fn var_error(&self, var: usize, value: usize) -> Option<f64> {
+        return self.errors.get(&VarAndValue{ var, value }).cloned();
+    }
 }
 
 impl EntropyOptimizer {
+    // Fails unless every constraint fits the problem.
+    pub fn new(varc: usize, k: usize, contras: HashSet<EntropyConstraint>) -> Result<EntropyOptimizer, String> {
+        if k == 0 {
+            return Err("A problem needs at least one value.".to_string());
+        }
+        for contra in contras.iter() {
+            try!(contra.check(varc, k));
+        }
+        return Ok(EntropyOptimizer{ varc, k, contras });
+    }
+
     fn required_joints(&self) -> Vec<(usize, usize)>  {
         // Remove joint probabilities where possible.
-        let mut required_joints: Vec<(usize, usize)> = Vec::new();
-        for &contra in self.contras.iter() {
-            if let EntropyConstraint::DoubleNeq(first, second) = contra {
-                required_joints.push((first, second));
+        let mut required_joints: BTreeSet<(usize, usize)> = BTreeSet::new();
+        for contra in self.contras.iter() {
+            let pairs: Vec<(usize, usize)> = match *contra {
+                EntropyConstraint::DoubleNeq(first, second) => vec![(first, second)],
+                EntropyConstraint::DoubleEq(first, second) => vec![(first, second)],
+                EntropyConstraint::JointNeq(first, second) => vec![(first.var, second.var)],
+                // At most one of the variables can take the value, so each pair of them is a joint.
+                EntropyConstraint::ExactlyOne(ref vars, _) => vars.iter()
+                    .flat_map(|&first| vars.iter().map(move |&second| (first, second)))
+                    .filter(|&(first, second)| first < second)
+                    .collect(),
+                // Same within each group, and chain the groups so even single variables get a joint.
+                EntropyConstraint::AtLeast(ref groups, _, _) => {
+                    let mut pairs: Vec<(usize, usize)> = groups.iter()
+                        .flat_map(|group| group.iter().flat_map(move |&first| group.iter().map(move |&second| (first, second))))
+                        .filter(|&(first, second)| first < second)
+                        .collect();
+                    for i in 1..groups.len() {
+                        pairs.push((groups[i - 1][0], groups[i][0]));
+                    }
+                    pairs
+                },
+                EntropyConstraint::SingleNeq(_) | EntropyConstraint::SingleEq(_) => Vec::new()
+            };
+            for (first, second) in pairs {
+                required_joints.insert((cmp::min(first, second), cmp::max(first, second)));
+            }
+        }
+
+        return required_joints.into_iter().collect();
+    }
+
+    pub fn optimize(&self) -> Result<OptimizationResult, String> {
+        return self.optimize_with(Method::Newton, &Method::Newton.default_options());
+    }
+
+    // Components are solved on their own, in parallel, and since they share no
+    // variables the entropy of the merged result is the sum of theirs.
+    pub fn optimize_with(&self, method: Method, options: &Options) -> Result<OptimizationResult, String> {
+        return self.solve(method, options, None);
+    }
+
+    // Solves again after constraints were added to the problem the previous result solved, with its method
+    // and options. Only components whose constraints changed or that had not converged are solved again.
+    pub fn optimize_from(&self, previous: &OptimizationResult) -> Result<OptimizationResult, String> {
+        return self.solve(previous.method, &previous.options, Some(previous));
+    }
+
+    // Newton and Scaling start from the multipliers of the previous result where it has them.
+    // Components it solved to convergence with the same constraints, method and options are copied from it.
+    fn solve(&self, method: Method, options: &Options, previous: Option<&OptimizationResult>) -> Result<OptimizationResult, String> {
+        let reusable = previous
+            .filter(|previous| previous.method == method && previous.options == *options)
+            .and_then(|previous| previous.problem.as_ref().map(|problem| (previous, problem)))
+            .filter(|&(_, problem)| problem.varc == self.varc && problem.k == self.k);
+        let (unchanged, changed): (Vec<EntropySubProblem>, Vec<EntropySubProblem>) = self.separate().into_iter()
+            .partition(|sub| reusable.is_some_and(|(previous, problem)| {
+                sub.unchanged_since(problem) && !sub.var_map.iter().any(|var| previous.unconverged.contains(var))
+            }));
+
+        let solved: Vec<(EntropySubProblem, OptimizationResult)> = try!(changed.into_par_iter()
+            .map(|sub| -> Result<(EntropySubProblem, OptimizationResult), String> {
+                let warm = |key: &DualKey| -> Option<f64> {
+                    previous.and_then(|previous| previous.duals.get(&key.originals(&sub)[0]).cloned())
+                };
+                let result = try!(sub.optimizer.optimize_component(&sub.counts(), method, options, &warm));
+                Ok((sub, result))
+            })
+            .collect());
+
+        let mut distribution: HashMap<VarAndValue, f64> = HashMap::new();
+        let mut errors: HashMap<VarAndValue, f64> = HashMap::new();
+        let mut joint_marginals: HashMap<(usize, VarAndValue), f64> = HashMap::new();
+        let mut joints: HashMap<(usize, usize), JointTable> = HashMap::new();
+        let mut duals: HashMap<DualKey, f64> = HashMap::new();
+        let mut unconverged: BTreeSet<usize> = BTreeSet::new();
+        for (sub, result) in solved {
+            sub.expand(&result.distribution, &mut distribution);
+            sub.expand(&result.errors, &mut errors);
+            sub.expand_joint_marginals(&result.joint_marginals, &mut joint_marginals);
+            sub.expand_joints(&result.joints, self.k, &mut joints);
+            for (key, &value) in result.duals.iter() {
+                for original in key.originals(&sub) {
+                    duals.insert(original, value);
+                }
             }
+            unconverged.extend(result.unconverged.iter().map(|&var| sub.var_map[var]));
         }
 
-        return required_joints;
+        if let Some((previous, _)) = reusable {
+            let kept: HashSet<usize> = unchanged.iter().flat_map(|sub| sub.var_map.iter().cloned()).collect();
+            previous.copy_vars(&kept, &mut distribution, &mut errors, &mut joint_marginals, &mut joints, &mut duals);
+        }
+
+        return Ok(OptimizationResult{
+            distribution,
+            errors,
+            joint_marginals,
+            joints,
+            duals,
+            problem: Some(self.clone()),
+            method,
+            options: *options,
+            unconverged,
+            k: self.k,
+            varc: self.varc
+        });
+    }
+
+    // A result with only marginals, for the methods that do not solve joints.
+    fn marginals_result(&self, distribution: HashMap<VarAndValue, f64>, errors: HashMap<VarAndValue, f64>, unconverged: BTreeSet<usize>)
+        -> OptimizationResult
+    {
+        return OptimizationResult{
+            distribution,
+            errors,
+            joint_marginals: HashMap::new(),
+            joints: HashMap::new(),
+            duals: HashMap::new(),
+            problem: None,
+            method: Method::Newton,
+            options: Options::default(),
+            unconverged,
+            k: self.k,
+            varc: self.varc
+        };
     }
 
-    pub fn optimize(&self) -> OptimizationResult {
+    // Value v stands for counts[v] values of the original problem, which no constraint tells
+    // apart. Presolve, exact, sampler and propagation take counts the same way. Warm gives the
+    // multiplier a previous solve found for a key, if any.
+    fn optimize_component<W: Fn(&DualKey) -> Option<f64>>(&self, counts: &[f64], method: Method, options: &Options, warm: &W)
+        -> Result<OptimizationResult, String>
+    {
+        if method == Method::Exact {
+            let marginals = try!(exact::marginals(self, counts, options));
+            let unconverged = (0..self.varc).filter(|_| !marginals.converged).collect();
+            return Ok(self.marginals_result(marginals.distribution, HashMap::new(), unconverged));
+        }
+        if method == Method::Sample {
+            let estimate = try!(sampler::marginals(self, counts, options.max_iter));
+            return Ok(self.marginals_result(estimate.marginals, estimate.errors, estimate.unconverged));
+        }
+        if method == Method::Propagation {
+            let marginals = try!(propagation::marginals(self, counts, options));
+            let converged = marginals.reason == StopReason::AbsoluteResidual;
+            let unconverged = (0..self.varc).filter(|_| !converged).collect();
+            return Ok(self.marginals_result(marginals.distribution, HashMap::new(), unconverged));
+        }
+
+        let domains = try!(Domains::new(self, counts));
         let mut var_meaning: Vec<VariableType> = Vec::new();
-        let mut lagrangians: Vec<Vec<usize>> = Vec::new();
+        let mut lagrangians: Vec<(Vec<usize>, DualKey)> = Vec::new();
+        let mut keys: HashMap<usize, DualKey> = HashMap::new();
         let mut partials: HashMap<PartialLagrangian, Vec<usize>> = HashMap::new();
         let required_joints = self.required_joints();
-        let mentioned: HashSet<usize> = required_joints.iter().flat_map(|&(one, two)| vec![one, two]).collect();
+        // Ordered, so the same joints are equated the same way from one solve to the next.
+        let mentioned: BTreeSet<usize> = required_joints.iter().flat_map(|&(one, two)| vec![one, two]).collect();
         let mut distribution: HashMap<VarAndValue, f64> = HashMap::new();
 
         fn add_partials(
@@ -92,7 +573,7 @@ impl EntropyOptimizer {
         {
             partials
                 .entry(PartialLagrangian{ given, free: free.var})
-                .or_insert_with(|| Vec::new())
+                .or_default()
                 .push(current_pos);
         }
 
@@ -100,31 +581,74 @@ impl EntropyOptimizer {
             let mut sum_to_one: Vec<usize> = Vec::new();
             for k1 in 0..self.k {
                 for k2 in 0..self.k {
-                    if !self.is_constrained(n1, k1, n2, k2) {
+                    let count = domains.cell_count(n1, k1, n2, k2);
+                    if count > 0.0 {
                         let var1 = VarAndValue{ var: n1, value: k1 };
                         let var2 = VarAndValue{ var: n2, value: k2 };
                         let current_pos = var_meaning.len();
                         add_partials(&mut partials, var1, var2, current_pos);
                         add_partials(&mut partials, var2, var1, current_pos);
                         sum_to_one.push(var_meaning.len());
-                        var_meaning.push(BaseVariable{ var1, var2 , lagrangians: Vec::new(), neg_lags: Vec::new()});
+                        var_meaning.push(BaseVariable{ var1, var2, count, lagrangians: Vec::new(), neg_lags: Vec::new()});
                     }
                 }
             }
 
-            lagrangians.push(sum_to_one);
+            lagrangians.push((sum_to_one, DualKey::SumToOne(n1, n2)));
+        }
+
+        for contra in self.contras.iter() {
+            if let EntropyConstraint::ExactlyOne(ref vars, value) = *contra {
+                // A single variable is just SingleEq and is handled by the domains.
+                if vars.len() < 2 {
+                    continue;
+                }
+                let mut exactly_one: Vec<usize> = vars.iter()
+                    .flat_map(|&var| {
+                        let other = partner(&required_joints, var).expect("ExactlyOne variables are in required_joints.");
+                        marginal_cells(&var_meaning, VarAndValue{ var, value }, other)
+                    })
+                    .collect();
+                exactly_one.sort();
+                lagrangians.push((exactly_one, DualKey::Constraint(contra.clone())));
+            }
         }
 
-        for lag in lagrangians {
+        let first_lagrangian = var_meaning.len();
+        for (lag, key) in lagrangians {
             let lagind = var_meaning.len();
             for &i in lag.iter() {
                 if let BaseVariable{ ref mut lagrangians, .. } = var_meaning[i] {
                     lagrangians.push(lagind);
                 }
             }
+            keys.insert(lagind, key);
             var_meaning.push(Lagrangian(lag));
         }
 
+        for contra in self.contras.iter() {
+            if let EntropyConstraint::AtLeast(ref groups, count, value) = *contra {
+                let mut at_least: Vec<usize> = groups.iter()
+                    .flat_map(|group| group.iter())
+                    .flat_map(|&var| {
+                        let other = partner(&required_joints, var).expect("AtLeast variables are in required_joints.");
+                        marginal_cells(&var_meaning, VarAndValue{ var, value }, other)
+                    })
+                    .collect();
+                at_least.sort();
+
+                let lagind = var_meaning.len();
+                for &i in at_least.iter() {
+                    if let BaseVariable{ ref mut lagrangians, .. } = var_meaning[i] {
+                        lagrangians.push(lagind);
+                    }
+                }
+                // When every group must take the value the count is exact.
+                keys.insert(lagind, DualKey::Constraint(contra.clone()));
+                var_meaning.push(CountLagrangian{ sum: at_least, target: count as f64, at_least: count < groups.len() });
+            }
+        }
+
         // If no joints, can just assume uniform distribution!
         for n in 0..self.varc {
             if mentioned.contains(&n) {
@@ -132,23 +656,21 @@ impl EntropyOptimizer {
             }
             let mut variables: Vec<VarAndValue> = Vec::new();
             for k in 0..self.k {
-                let varval = VarAndValue{ var: n, value: k };
-                let contra = EntropyConstraint::SingleNeq(varval);
-                if !self.contras.contains(&contra) {
-                    variables.push(varval);
+                if domains.allows(n, k) {
+                    variables.push(VarAndValue{ var: n, value: k });
                 }
             }
 
-            let prob = 1.0 / (variables.len() as f64);
+            let total: f64 = variables.iter().map(|var| counts[var.value]).sum();
             for var in variables {
-                distribution.insert(var, prob);
+                distribution.insert(var, counts[var.value] / total);
             }
         }
 
         // Only need to check mentioned values for equivalencies.
         for &n1 in mentioned.iter() {
             for k in 0..self.k {
-                let mut to_eq: Vec<Vec<usize>> = Vec::new();
+                let mut to_eq: Vec<(usize, Vec<usize>)> = Vec::new();
                 let given = VarAndValue{ var: n1, value: k };
                 for &n2 in mentioned.iter() {
                     if n1 == n2 {
@@ -156,189 +678,423 @@ impl EntropyOptimizer {
                     }
                     let part = PartialLagrangian{ given, free: n2 };
                     if let Some(entry) = partials.get(&part) {
-                        to_eq.push(entry.clone());
+                        to_eq.push((n2, entry.clone()));
                     }
                 }
 
-                if to_eq.len() > 1 {
-                    for i1 in 0..to_eq.len() {
-                        for i2 in 0..to_eq.len() {
-                            if i1 == i2 {
-                                continue;
-                            }
-                            let lagind = var_meaning.len();
-                            for &child in to_eq[i1].iter() {
-                                if let BaseVariable{ ref mut lagrangians, .. } = var_meaning[child] {
-                                    lagrangians.push(lagind);
-                                } else {
-                                    panic!("Unexpected variable type.")
-                                }
-                            }
-                            for &child in to_eq[i2].iter() {
-                                if let BaseVariable{ ref mut neg_lags, .. } = var_meaning[child] {
-                                    neg_lags.push(lagind);
-                                } else {
-                                    panic!("Unexpected variable type.")
-                                }
-                            }
-                            var_meaning.push(EquivalentSums(to_eq[i1].clone(), to_eq[i2].clone()))
+                // Equating the first joint with each of the others is enough, any more are redundant.
+                for i2 in 1..to_eq.len() {
+                    let lagind = var_meaning.len();
+                    for &child in to_eq[0].1.iter() {
+                        if let BaseVariable{ ref mut lagrangians, .. } = var_meaning[child] {
+                            lagrangians.push(lagind);
+                        } else {
+                            panic!("Unexpected variable type.")
+                        }
+                    }
+                    for &child in to_eq[i2].1.iter() {
+                        if let BaseVariable{ ref mut neg_lags, .. } = var_meaning[child] {
+                            neg_lags.push(lagind);
+                        } else {
+                            panic!("Unexpected variable type.")
                         }
                     }
+                    keys.insert(lagind, DualKey::Equivalent(given, to_eq[0].0, to_eq[i2].0));
+                    var_meaning.push(EquivalentSums(to_eq[0].1.clone(), to_eq[i2].1.clone()))
                 }
             }
         }
 
         let size = var_meaning.len();
-        let gradient = EntropyGradient{ var_meaning: var_meaning.clone() };
-        let start = DynVector::from_element(size, 0.5);
-        let result = gradient_descent::optimize(&gradient, start);
+        if size == 0 {
+            return Ok(self.marginals_result(distribution, HashMap::new(), BTreeSet::new()));
+        }
+        let cold = start_point(&var_meaning, first_lagrangian, required_joints.len());
+        let start = warm_start(&var_meaning, &keys, cold, warm);
+        let solution = try!(match method {
+            Method::Newton => dual::optimize(&var_meaning, &start, options),
+            Method::Scaling => scaling::optimize(&var_meaning, start, options),
+            Method::Exact | Method::Sample | Method::Propagation => unreachable!()
+        });
+        let result = &solution.x;
 
-        for n in 0..self.varc {
+        // The EquivalentSums make the joints agree on shared variables only to the tolerance,
+        // so every joint's view is kept and the distribution gets their mean.
+        let mut joint_marginals: HashMap<(usize, VarAndValue), f64> = HashMap::new();
+        for &(n1, n2) in required_joints.iter() {
             for k in 0..self.k {
-                let varval = VarAndValue{ var: n, value: k };
-                if mentioned.contains(&n) {
-                    let other = required_joints.iter()
-                        .filter(|&&(first, second)| first == n || second == n)
-                        .next()
-                        .map(|&(first, second)| {
-                            if first == n {
-                                second
-                            } else {
-                                first
-                            }
-                        })
-                        .expect("If mentioned, should be in required_joints.");
-                    let probability: f64 = var_meaning.iter().enumerate()
-                        .filter(|&(_, meaning)| {
-                            if let &BaseVariable{ var1, var2, .. } = meaning {
-                                (var1 == varval && var2.var == other) ||
-                                (var2 == varval && var1.var == other)
-                            } else {
-                                false
-                            }
-                        })
-                        .map(|(i, _)| result[i]).sum();
-                    distribution.insert(varval, probability);
-                }
+                joint_marginals.insert((n2, VarAndValue{ var: n1, value: k }), 0.0);
+                joint_marginals.insert((n1, VarAndValue{ var: n2, value: k }), 0.0);
+            }
+        }
+        for (i, meaning) in var_meaning.iter().enumerate() {
+            if let BaseVariable{ var1, var2, .. } = *meaning {
+                *joint_marginals.get_mut(&(var2.var, var1)).expect("Every cell is in a required joint.") += result[i];
+                *joint_marginals.get_mut(&(var1.var, var2)).expect("Every cell is in a required joint.") += result[i];
             }
         }
+        let mut cells: HashMap<(usize, usize), HashMap<(usize, usize), f64>> = HashMap::new();
+        for (i, meaning) in var_meaning.iter().enumerate() {
+            if let BaseVariable{ var1, var2, .. } = *meaning {
+                cells.entry((var1.var, var2.var)).or_default().insert((var1.value, var2.value), result[i]);
+            }
+        }
+        let joints: HashMap<(usize, usize), JointTable> = cells.into_iter()
+            .map(|(pair, cells)| (pair, JointTable::new(self.k, cells)))
+            .collect();
+
+        let mut joints_per_var: HashMap<usize, f64> = HashMap::new();
+        for &(n1, n2) in required_joints.iter() {
+            *joints_per_var.entry(n1).or_insert(0.0) += 1.0;
+            *joints_per_var.entry(n2).or_insert(0.0) += 1.0;
+        }
+        for (&(_, varval), &probability) in joint_marginals.iter() {
+            *distribution.entry(varval).or_insert(0.0) += probability / joints_per_var[&varval.var];
+        }
+
+        let duals: HashMap<DualKey, f64> = keys.into_iter().map(|(i, key)| (key, result[i])).collect();
 
-        return OptimizationResult{ 
+        return Ok(OptimizationResult{
             distribution,
+            errors: HashMap::new(),
+            joint_marginals,
+            joints,
+            duals,
+            problem: None,
+            method,
+            options: *options,
+            unconverged: if solution.converged() { BTreeSet::new() } else { (0..self.varc).collect() },
             k: self.k,
             varc: self.varc
-        }
+        });
     }
+}
 
-    fn is_constrained(&self, var1: usize, val1: usize, var2: usize, val2: usize) -> bool {
-        self.contras.iter().any(|contra| {
-            match *contra {
-                EntropyConstraint::DoubleNeq(test1, test2) => {
-                    return val1 == val2 && ((var1 == test1 && var2 == test2) || (var1 == test2 && var2 == test1));
-                },
-                EntropyConstraint::SingleNeq(varval) => {
-                    return (var1 == varval.var && val1 == varval.value) || (var2 == varval.var && val2 == varval.value);
-                }
+// The variable paired with var in its first joint. Constraints on its marginal are written against that joint.
+fn partner(required_joints: &[(usize, usize)], var: usize) -> Option<usize> {
+    return required_joints.iter()
+        .find(|&&(first, second)| first == var || second == var)
+        .map(|&(first, second)| {
+            if first == var {
+                second
+            } else {
+                first
+            }
+        });
+}
+
+fn marginal_cells(var_meaning: &[VariableType], varval: VarAndValue, other: usize) -> Vec<usize> {
+    return var_meaning.iter().enumerate()
+        .filter(|&(_, meaning)| {
+            if let &BaseVariable{ var1, var2, .. } = meaning {
+                (var1 == varval && var2.var == other) ||
+                (var2 == varval && var1.var == other)
+            } else {
+                false
             }
         })
+        .map(|(i, _)| i)
+        .collect();
+}
+
+// Each joint starts uniform over its cells, which is optimal when it only has to sum to one.
+// The sum to one Lagrangians come first and are in the same order as the joints.
+fn start_point(var_meaning: &[VariableType], first_lagrangian: usize, joints: usize) -> DynVector {
+    let mut start = DynVector::from_element(var_meaning.len(), 0.0);
+    for lagind in first_lagrangian..(first_lagrangian + joints) {
+        if let Lagrangian(ref cells) = var_meaning[lagind] {
+            let count = |i: usize| {
+                if let BaseVariable{ count, .. } = var_meaning[i] {
+                    count
+                } else {
+                    panic!("Unexpected variable type.")
+                }
+            };
+            let total: f64 = cells.iter().map(|&i| count(i)).sum();
+            for &i in cells.iter() {
+                start[i] = count(i) / total;
+            }
+            start[lagind] = MULT * (total.ln() - 1.0);
+        }
     }
+    return start;
 }
 
-const MULT: f64 = 1.0;
+// Starts from the multipliers a previous solve found, with the rest as in the cold start, and the
+// cells at their optimum for those multipliers. That is where Scaling needs them, and
+// Newton only has to move as far as the new constraints push. Falls back to the cold start when
+// nothing carries over or a cell would underflow.
+fn warm_start<W: Fn(&DualKey) -> Option<f64>>(var_meaning: &[VariableType], keys: &HashMap<usize, DualKey>, cold: DynVector, warm: &W)
+    -> DynVector
+{
+    let mut start = cold.clone();
+    let mut found = false;
+    for (&i, key) in keys.iter() {
+        if let Some(value) = warm(key) {
+            start[i] = value;
+            found = true;
+        }
+    }
+    if !found {
+        return cold;
+    }
 
-struct EntropyGradient {
-    var_meaning: Vec<VariableType>
+    for (i, meaning) in var_meaning.iter().enumerate() {
+        if let BaseVariable{ count, ref lagrangians, ref neg_lags, .. } = *meaning {
+            let lag_sum: f64 = lagrangians.iter().map(|&i2| start[i2]).sum();
+            let neg_sum: f64 = neg_lags.iter().map(|&i2| start[i2]).sum();
+            start[i] = count * (-1.0 - (lag_sum - neg_sum) / MULT).exp();
+            if !(start[i] > 0.0 && start[i].is_finite()) {
+                return cold;
+            }
+        }
+    }
+    return start;
 }
 
-impl gradient_descent::Gradient for EntropyGradient {
-    fn gradient(&self, x: &VectorN<f64, Dynamic>) -> VectorN<f64, Dynamic> {
-        let mut result: VectorN<f64, Dynamic> = DynVector::from_element(x.len(), 0.0);
-        for (i, var_type) in self.var_meaning.iter().enumerate() {
-            match var_type {
-                &BaseVariable{ ref lagrangians, ref neg_lags, .. } => {
-                    let prob_part = MULT * (x[i].ln() + 1.0);
-                    let lag_sum: f64 = lagrangians.iter().map(|&i2| x[i2]).sum();
-                    let neg_sum: f64 = neg_lags.iter().map(|&i2| x[i2]).sum();
-                    result[i] = prob_part + lag_sum - neg_sum;
-                },
-                &Lagrangian(ref sum_to_one ) => {
-                    let sum: f64 = sum_to_one.iter().map(|&i2| x[i2]).sum();
-                    result[i] = sum - 1.0;
-                },
-                &EquivalentSums(ref to_add, ref to_min) => {
-                    let sum: f64 = to_add.iter().map(|&i2| x[i2]).sum();
-                    let minus: f64 = to_min.iter().map(|&i2| x[i2]).sum();
-                    result[i] = sum - minus;
-                }
+pub const MULT: f64 = 1.0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use solver::EntropyConstraint::*;
+
+    fn problem(varc: usize, k: usize, contras: Vec<EntropyConstraint>) -> EntropyOptimizer {
+        return EntropyOptimizer{ varc, k, contras: contras.into_iter().collect() };
+    }
+
+    // Newton and Scaling must both converge to the same marginals.
+    fn assert_marginals(optimizer: &EntropyOptimizer, expected: &[(usize, usize, f64)]) {
+        for &method in [Method::Newton, Method::Scaling].iter() {
+            let result = optimizer.optimize_with(method, &method.default_options()).unwrap();
+            assert!(result.converged(), "{} did not converge", method);
+            for &(var, value, prob) in expected.iter() {
+                let found = result.var_prob(var, value);
+                assert!((found - prob).abs() < 1e-9, "{}: Pr[{}={}] = {}, expected {}", method, var, value, found, prob);
             }
         }
+    }
 
-        return result;
-    }
-
-    fn hessian(&self, x: &VectorN<f64, Dynamic>) -> MatrixN<f64, Dynamic> {
-        DynMatrix::from_fn(x.len(), x.len(), |row, column| {
-            // Row is the first partial, column is the second one.
-            match self.var_meaning.get(row).unwrap() {
-                &BaseVariable{..} => {
-                    match self.var_meaning.get(column).unwrap() {
-                        &BaseVariable{ .. } => {
-                            return if row == column {
-                                MULT / x[row]
-                            } else {
-                                0.0
-                            };
-                        },
-                        &Lagrangian(ref sum_to_one) => {
-                            return if sum_to_one.binary_search(&row).is_ok() {
-                                1.0
-                            } else {
-                                0.0
-                            };
-                        },
-                        &EquivalentSums(ref pos, ref neg) => {
-                            return if pos.binary_search(&row).is_ok() {
-                                1.0
-                            } else if neg.binary_search(&row).is_ok() {
-                                -1.0
-                            } else {
-                                0.0
-                            }
-                        }
-                    }
-                },
-                &Lagrangian{ .. } => {
-                    match self.var_meaning.get(column).unwrap() {
-                        &BaseVariable{ ref lagrangians, .. } => {
-                            return if lagrangians.binary_search(&row).is_ok() {
-                                1.0
-                            } else {
-                                0.0
-                            };
-                        },
-                        _ => {
-                            return 0.0;
-                        }
-                    }
-                },
-                &EquivalentSums(_, _) => {
-                    match self.var_meaning.get(column).unwrap() {
-                        &BaseVariable{ ref lagrangians, ref neg_lags, .. } => {
-                            return if lagrangians.binary_search(&row).is_ok() {
-                                1.0
-                            } else if neg_lags.binary_search(&row).is_ok() {
-                                -1.0
-                            } else {
-                                0.0
-                            };
-                        },
-                        _ => {
-                            return 0.0;
-                        }
-                    }
-                }
+    #[test]
+    fn problems_and_results_read_back_what_was_written() {
+        let optimizer = problem(3, 3, vec![
+            ExactlyOne(vec![0, 1, 2], 2),
+            DoubleNeq(0, 1),
+            SingleNeq(VarAndValue{ var: 0, value: 1 })
+        ]);
+        let read: EntropyOptimizer = serde_json::from_str(&serde_json::to_string(&optimizer).unwrap()).unwrap();
+        assert_eq!((read.varc, read.k), (3, 3));
+        assert_eq!(read.contras, optimizer.contras);
+
+        let result = optimizer.optimize().unwrap();
+        let read: OptimizationResult = serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert_eq!(read.converged(), result.converged());
+        for var in 0..3 {
+            for value in 0..3 {
+                assert_eq!(read.var_prob(var, value), result.var_prob(var, value));
             }
-        })
+        }
+    }
+
+    #[test]
+    fn json_constraints_must_fit_the_problem() {
+        let contras = [
+            r#"{"DoubleNeq":[0,5]}"#,
+            r#"{"DoubleEq":[1,1]}"#,
+            r#"{"JointNeq":[{"var":0,"value":0},{"var":0,"value":1}]}"#,
+            r#"{"ExactlyOne":[[0,1,0],1]}"#,
+            r#"{"SingleEq":{"var":0,"value":2}}"#,
+            r#"{"ExactlyOne":[[],0]}"#,
+            r#"{"AtLeast":[[],0,0]}"#,
+            r#"{"AtLeast":[[[0],[]],1,0]}"#,
+            r#"{"AtLeast":[[[0]],2,0]}"#
+        ];
+        for contra in contras.iter() {
+            let json = format!(r#"{{"varc":2,"k":2,"contras":[{}]}}"#, contra);
+            assert!(serde_json::from_str::<EntropyOptimizer>(&json).is_err(), "{} was accepted", contra);
+        }
+        assert!(serde_json::from_str::<EntropyOptimizer>(r#"{"varc":2,"k":2,"contras":[{"DoubleNeq":[0,1]}]}"#).is_ok());
+
+        let result = r#"{"varc":1,"k":2,"converged":true,"entropy":0,"distribution":[{"var":1,"value":0,"probability":1}]}"#;
+        assert!(serde_json::from_str::<OptimizationResult>(result).is_err());
+    }
+
+    #[test]
+    fn exact_and_sample_say_whether_they_converged() {
+        let optimizer = problem(4, 3, vec![ExactlyOne(vec![0, 1, 2], 2), DoubleNeq(0, 1), DoubleNeq(2, 3)]);
+        let exact = optimizer.optimize_with(Method::Exact, &Method::Exact.default_options()).unwrap();
+        assert!(exact.converged());
+        let sample = optimizer.optimize_with(Method::Sample, &Method::Sample.default_options()).unwrap();
+        assert!(sample.converged());
+        for var in 0..4 {
+            for value in 0..3 {
+                assert!((sample.var_prob(var, value) - exact.var_prob(var, value)).abs() < 0.02);
+            }
+        }
+
+        // Chains start apart, so a single sweep cannot look settled.
+        let short = Options{ max_iter: 1, ..Method::Sample.default_options() };
+        assert!(!optimizer.optimize_with(Method::Sample, &short).unwrap().converged());
+    }
+
+    #[test]
+    fn sample_moves_variables_tied_by_eq_together() {
+        let optimizer = problem(4, 4, vec![ExactlyOne(vec![0, 1, 2], 0), DoubleNeq(0, 3), DoubleNeq(1, 3), DoubleEq(2, 3)]);
+        let exact = optimizer.optimize_with(Method::Exact, &Method::Exact.default_options()).unwrap();
+        assert!((exact.var_prob(0, 0) - 6.0 / 21.0).abs() < 1e-12);
+        assert!((exact.var_prob(2, 0) - 9.0 / 21.0).abs() < 1e-12);
+        let sample = optimizer.optimize_with(Method::Sample, &Method::Sample.default_options()).unwrap();
+        assert!(sample.converged());
+        for var in 0..4 {
+            for value in 0..4 {
+                assert!((sample.var_prob(var, value) - exact.var_prob(var, value)).abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn a_hub_in_several_joints_gets_their_mean() {
+        let optimizer = problem(4, 3, vec![
+            DoubleNeq(0, 1),
+            DoubleNeq(0, 2),
+            DoubleEq(0, 3),
+            SingleNeq(VarAndValue{ var: 1, value: 0 }),
+            SingleEq(VarAndValue{ var: 2, value: 2 })
+        ]);
+        let options = Method::Newton.default_options();
+        let result = optimizer.optimize_with(Method::Newton, &options).unwrap();
+        assert!(result.converged());
+        assert_eq!(result.partners(0), vec![1, 2, 3]);
+        assert_eq!(result.partners(3), vec![0]);
+        for value in 0..3 {
+            let seen: Vec<f64> = result.partners(0).iter().map(|&other| result.joint_marginal(0, other, value).unwrap()).collect();
+            let mean = seen.iter().sum::<f64>() / seen.len() as f64;
+            assert!((result.var_prob(0, value) - mean).abs() < 1e-12);
+        }
+        assert!(result.disagreement(0) <= result.max_disagreement());
+        assert!(result.max_disagreement() < options.absolute_tolerance);
+        assert!(result.var_prob(0, 2) < 1e-12);
+    }
+
+    #[test]
+    fn joints_of_lumped_values_split_by_the_constraint() {
+        let result = problem(2, 3, vec![DoubleEq(0, 1)]).optimize().unwrap();
+        assert_eq!(result.joint_pairs(), vec![(0, 1)]);
+        assert!((result.joint_prob(1, 2, 0, 2).unwrap() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(result.joint_prob(0, 0, 1, 1), Some(0.0));
+        assert!((result.mutual_information(1, 0).unwrap() - 3f64.log2()).abs() < 1e-9);
+
+        let result = problem(3, 3, vec![DoubleNeq(0, 1)]).optimize().unwrap();
+        assert!((result.joint_prob(0, 0, 1, 1).unwrap() - 1.0 / 6.0).abs() < 1e-9);
+        assert_eq!(result.joint_prob(0, 0, 2, 0), None);
+    }
+
+    #[test]
+    fn unconstrained_is_uniform() {
+        let third = 1.0 / 3.0;
+        assert_marginals(&problem(2, 3, Vec::new()), &[(0, 0, third), (1, 2, third)]);
+        assert_marginals(&problem(2, 3, vec![DoubleNeq(0, 1)]), &[(0, 0, third), (0, 1, third), (1, 2, third)]);
+        assert_marginals(&problem(2, 3, vec![DoubleEq(0, 1)]), &[(0, 0, third), (1, 1, third), (1, 2, third)]);
+    }
+
+    #[test]
+    fn joint_neq_leaves_three_equal_cells() {
+        let first = VarAndValue{ var: 0, value: 0 };
+        let second = VarAndValue{ var: 1, value: 0 };
+        let third = 1.0 / 3.0;
+        assert_marginals(&problem(2, 2, vec![JointNeq(first, second)]), &[(0, 0, third), (0, 1, 2.0 * third), (1, 0, third)]);
+    }
+
+    #[test]
+    fn exactly_one_splits_the_value_evenly() {
+        assert_marginals(&problem(2, 2, vec![ExactlyOne(vec![0, 1], 0)]), &[(0, 0, 0.5), (0, 1, 0.5), (1, 0, 0.5)]);
+        let third = 1.0 / 3.0;
+        let optimizer = problem(3, 3, vec![ExactlyOne(vec![0, 1, 2], 0)]);
+        assert_marginals(&optimizer, &[(0, 0, third), (1, 0, third), (2, 0, third), (0, 1, third), (2, 2, third)]);
+    }
+
+    #[test]
+    fn at_least_already_met_changes_nothing() {
+        let optimizer = problem(3, 2, vec![AtLeast(vec![vec![0], vec![1], vec![2]], 1, 0)]);
+        assert_marginals(&optimizer, &[(0, 0, 0.5), (1, 0, 0.5), (2, 0, 0.5)]);
+    }
+
+    #[test]
+    fn at_least_short_of_its_count_is_met_exactly() {
+        // Variable 1 is in both joints, so its entropy counts twice, and with f(p) the entropy of
+        // a value having p and the other three sharing the rest, f'(p0) = 2 f'(p1) = f'(p2).
+        let optimizer = problem(3, 4, vec![AtLeast(vec![vec![0], vec![1], vec![2]], 1, 0)]);
+        let slope = |p: f64| ((1.0 - p) / (3.0 * p)).ln();
+        for &method in [Method::Newton, Method::Scaling].iter() {
+            let result = optimizer.optimize_with(method, &method.default_options()).unwrap();
+            assert!(result.converged());
+            let (p0, p1, p2) = (result.var_prob(0, 0), result.var_prob(1, 0), result.var_prob(2, 0));
+            assert!((p0 + p1 + p2 - 1.0).abs() < 1e-9);
+            assert!((p0 - p2).abs() < 1e-9);
+            assert!((slope(p0) - 2.0 * slope(p1)).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn fixed_values_rule_out_their_neighbours() {
+        let optimizer = problem(3, 3, vec![SingleEq(VarAndValue{ var: 0, value: 0 }), DoubleNeq(0, 1), DoubleNeq(1, 2)]);
+        assert_marginals(&optimizer, &[(0, 0, 1.0), (1, 1, 0.5), (1, 2, 0.5), (2, 0, 0.5), (2, 1, 0.25), (2, 2, 0.25)]);
+        let result = optimizer.optimize().unwrap();
+        assert_eq!(result.var_prob(0, 1), 0.0);
+        assert_eq!(result.var_prob(1, 0), 0.0);
     }
-}
 
+    #[test]
+    fn at_least_rules_out_pairs_that_leave_too_few_groups() {
+        // Neither of the two taking value 0 leaves no group that does, so five cells remain.
+        let optimizer = problem(2, 3, vec![AtLeast(vec![vec![0], vec![1]], 1, 0)]);
+        assert_marginals(&optimizer, &[(0, 0, 0.6), (0, 1, 0.2), (1, 0, 0.6), (1, 2, 0.2)]);
+    }
+
+    #[test]
+    fn only_changed_or_unconverged_components_are_solved_again() {
+        let before = problem(4, 3, vec![DoubleNeq(0, 1), DoubleNeq(2, 3)]);
+        let mut previous = before.optimize().unwrap();
+        // A value no solve would give, so a copy of the first component shows.
+        let marked = VarAndValue{ var: 0, value: 0 };
+        previous.distribution.insert(marked, 0.9);
+
+        let mut after = before.clone();
+        after.contras.insert(SingleNeq(VarAndValue{ var: 3, value: 1 }));
+        let result = after.optimize_from(&previous).unwrap();
+        assert_eq!(result.var_prob(0, 0), 0.9);
+        assert_eq!(result.var_prob(3, 1), 0.0);
+        assert!(result.converged());
+
+        previous.unconverged.insert(1);
+        let result = after.optimize_from(&previous).unwrap();
+        assert!((result.var_prob(0, 0) - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn conditioning_matches_solving_with_the_observation() {
+        let optimizer = problem(4, 3, vec![DoubleNeq(0, 1), DoubleNeq(2, 3)]);
+        let mut result = optimizer.optimize().unwrap();
+        let observation = vec![JointNeq(VarAndValue{ var: 2, value: 0 }, VarAndValue{ var: 3, value: 1 })];
+        let conditioned = result.condition(&observation).unwrap();
+
+        let mut with = optimizer.clone();
+        with.contras.extend(observation.iter().cloned());
+        let cold = with.optimize().unwrap();
+        assert!(conditioned.converged());
+        for var in 0..4 {
+            for value in 0..3 {
+                assert!((conditioned.var_prob(var, value) - cold.var_prob(var, value)).abs() < 1e-9);
+            }
+        }
+        assert!((conditioned.var_prob(2, 0) - 1.0 / 3.0).abs() > 1e-3);
+
+        // The component the observation leaves alone is copied rather than solved.
+        result.distribution.insert(VarAndValue{ var: 1, value: 2 }, 0.9);
+        assert_eq!(result.condition(&observation).unwrap().var_prob(1, 2), 0.9);
+
+        let read: OptimizationResult = serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert!(read.condition(&observation).is_err());
+    }
+}
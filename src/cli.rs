@@ -0,0 +1,232 @@
+use alchemy::{Method, OptimizationResult};
+use gradient_descent::Options;
+use planner;
+use problem::Problem;
+use problem;
+use serde::Serialize;
+use serde_json;
+use solver::EntropyConstraint;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::io;
+use std::str::FromStr;
+
+const USAGE: &str = "usage:
+    skyrim-alchemy solve [FILE] [--json] [--pairs] [--method newton|scaling|exact|sample|propagation]
+                         [--max-iter N] [--tolerance X]
+    skyrim-alchemy suggest [FILE] [--top N]
+    skyrim-alchemy add-observation FILE CONSTRAINT...
+    skyrim-alchemy show [FILE] [--json]
+
+Problems are read from FILE, or from stdin when FILE is missing or -.
+They are either in the text format or the JSON written by show --json.";
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let rest = &args[1..];
+    return match args[0].as_str() {
+        "solve" => solve(rest),
+        "suggest" => suggest(rest),
+        "add-observation" => add_observation(rest),
+        "show" => show(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        other => Err(format!("unknown command {}\n{}", other, USAGE))
+    };
+}
+
+fn read_input(path: Option<&String>) -> Result<String, String> {
+    let mut text = String::new();
+    let read = match path {
+        Some(path) if path != "-" => File::open(path).and_then(|mut file| file.read_to_string(&mut text)),
+        _ => io::stdin().read_to_string(&mut text)
+    };
+    try!(read.map_err(|err| format!("could not read {}: {}", path.map_or("stdin", |path| path.as_str()), err)));
+    return Ok(text);
+}
+
+fn read_problem(path: Option<&String>) -> Result<Problem, String> {
+    let text = try!(read_input(path));
+    let name = path.map_or("stdin", |path| path.as_str());
+    if text.trim().starts_with('{') {
+        let optimizer = try!(serde_json::from_str(&text).map_err(|err| format!("{}: {}", name, err)));
+        return Ok(Problem{ optimizer, var_names: BTreeMap::new(), value_names: BTreeMap::new() });
+    }
+    return problem::parse(&text).map_err(|err| format!("{}: {}", name, err));
+}
+
+// Pulls a flag out of the arguments, returning whether it was there.
+fn take_flag(args: &[String], flag: &str) -> (bool, Vec<String>) {
+    let rest: Vec<String> = args.iter().filter(|arg| *arg != flag).cloned().collect();
+    return (rest.len() != args.len(), rest);
+}
+
+// Pulls an option and its value out of the arguments.
+fn take_option(args: &[String], option: &str) -> Result<(Option<String>, Vec<String>), String> {
+    return match args.iter().position(|arg| arg == option) {
+        Some(i) => {
+            let value = try!(args.get(i + 1).ok_or(format!("{} needs a value", option)));
+            let rest = args[..i].iter().chain(args[(i + 2)..].iter()).cloned().collect();
+            Ok((Some(value.clone()), rest))
+        },
+        None => Ok((None, args.to_vec()))
+    };
+}
+
+fn parse_option<T: FromStr>(value: Option<String>, default: T) -> Result<T, String> {
+    return match value {
+        Some(value) => value.parse::<T>().map_err(|_| format!("expected a number, found {}", value)),
+        None => Ok(default)
+    };
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), String> {
+    let json = try!(serde_json::to_string_pretty(value).map_err(|err| err.to_string()));
+    println!("{}", json);
+    return Ok(());
+}
+
+// Joints only agree to the solver tolerance, so smaller gaps are not worth mentioning.
+const DISAGREEMENT_WARNING: f64 = 1e-6;
+
+fn print_result(result: &OptimizationResult) {
+    print!("{}", result);
+    println!("entropy: {}", result.entropy());
+    if !result.converged() {
+        println!("warning: did not converge");
+    }
+    let disagreement = result.max_disagreement();
+    if disagreement > DISAGREEMENT_WARNING {
+        println!("warning: joints disagree on marginals by up to {}", disagreement);
+    }
+}
+
+fn solve(args: &[String]) -> Result<(), String> {
+    let (json, args) = take_flag(args, "--json");
+    let (pairs, args) = take_flag(&args, "--pairs");
+    let (method, args) = try!(take_option(&args, "--method"));
+    let (max_iter, args) = try!(take_option(&args, "--max-iter"));
+    let (tolerance, args) = try!(take_option(&args, "--tolerance"));
+    let method = match method {
+        Some(method) => try!(method.parse::<Method>()),
+        None => Method::Newton
+    };
+    // Only Newton counts Newton iterations, so each method has its own defaults.
+    let defaults = method.default_options();
+    let options = Options{
+        max_iter: try!(parse_option(max_iter, defaults.max_iter)),
+        absolute_tolerance: try!(parse_option(tolerance, defaults.absolute_tolerance)),
+        ..defaults
+    };
+
+    let problem = try!(read_problem(args.first()));
+    let result = try!(problem.optimizer.optimize_with(method, &options));
+    if json {
+        return print_json(&result);
+    }
+    print_result(&result);
+    if pairs {
+        print_pairs(&problem, &result);
+    }
+    return Ok(());
+}
+
+// How much each solved pair of variables tells about each other, most first.
+fn print_pairs(problem: &Problem, result: &OptimizationResult) {
+    let mut pairs: Vec<(f64, usize, usize)> = result.joint_pairs().into_iter()
+        .filter_map(|(var1, var2)| result.mutual_information(var1, var2).map(|info| (info, var1, var2)))
+        .collect();
+    pairs.sort_by(|first, second| second.0.partial_cmp(&first.0).unwrap_or(Ordering::Equal));
+    for (info, var1, var2) in pairs {
+        println!("I[{}; {}] = {} bits", problem.var_name(var1), problem.var_name(var2), info);
+    }
+}
+
+fn show(args: &[String]) -> Result<(), String> {
+    let (json, args) = take_flag(args, "--json");
+    let problem = try!(read_problem(args.first()));
+    let optimizer = &problem.optimizer;
+    if json {
+        return print_json(optimizer);
+    }
+    print!("{}", problem::format(&problem));
+    println!("# {} variables, {} values, {} constraints", optimizer.varc, optimizer.k, optimizer.contras.len());
+    return Ok(());
+}
+
+fn add_observation(args: &[String]) -> Result<(), String> {
+    if args.len() < 2 {
+        return Err(format!("add-observation needs a file and a constraint\n{}", USAGE));
+    }
+
+    let path = &args[0];
+    let text = try!(read_input(Some(path)));
+    let problem = try!(problem::parse(&text).map_err(|err| format!("{}: {}", path, err)));
+    let contra = try!(problem.parse_constraint(&args[1..].join(" "))
+        .map_err(|err| format!("observation: {}", err)));
+
+    let mut file = try!(OpenOptions::new().append(true).open(path)
+        .map_err(|err| format!("could not open {}: {}", path, err)));
+    let separator = if text.is_empty() || text.ends_with('\n') { "" } else { "\n" };
+    try!(writeln!(file, "{}{}", separator, problem.format_constraint(&contra))
+        .map_err(|err| format!("could not write {}: {}", path, err)));
+    return Ok(());
+}
+
+// Ranks pairs of variables by how much learning whether they are equal would lower the entropy.
+fn suggest(args: &[String]) -> Result<(), String> {
+    let (top, args) = try!(take_option(args, "--top"));
+    let top = try!(parse_option(top, 10));
+
+    let problem = try!(read_problem(args.first()));
+    let optimizer = &problem.optimizer;
+    let result = try!(optimizer.optimize());
+    let current = result.entropy();
+
+    // Variables no constraint mentions are interchangeable, so only the mentioned ones are worth testing.
+    let mut mentioned: Vec<usize> = optimizer.contras.iter().flat_map(|contra| contra.variables()).collect();
+    mentioned.sort();
+    mentioned.dedup();
+
+    let mut suggestions: Vec<(f64, usize, usize)> = Vec::new();
+    for (i, &var1) in mentioned.iter().enumerate() {
+        for &var2 in mentioned[(i + 1)..].iter() {
+            let known = [
+                EntropyConstraint::DoubleNeq(var1, var2), EntropyConstraint::DoubleNeq(var2, var1),
+                EntropyConstraint::DoubleEq(var1, var2), EntropyConstraint::DoubleEq(var2, var1)
+            ];
+            if known.iter().any(|contra| optimizer.contras.contains(contra)) {
+                continue;
+            }
+            // The solved joint knows how the constraints tie the pair, and without one they are independent.
+            let equal: f64 = (0..optimizer.k)
+                .map(|value| {
+                    result.joint_prob(var1, value, var2, value)
+                        .unwrap_or_else(|| result.var_prob(var1, value) * result.var_prob(var2, value))
+                })
+                .sum();
+            if equal <= 0.0 || equal >= 1.0 {
+                continue;
+            }
+            let outcomes: planner::Outcomes = vec![
+                (equal, vec![EntropyConstraint::DoubleEq(var1, var2)]),
+                (1.0 - equal, vec![EntropyConstraint::DoubleNeq(var1, var2)])
+            ];
+            suggestions.push((current - try!(planner::expected_entropy(&result, &outcomes)), var1, var2));
+        }
+    }
+    suggestions.sort_by(|first, second| second.0.partial_cmp(&first.0).unwrap_or(Ordering::Equal));
+
+    println!("entropy: {}", current);
+    for &(gain, var1, var2) in suggestions.iter().take(top) {
+        println!("test {} {}: expected gain {}", problem.var_name(var1), problem.var_name(var2), gain);
+    }
+    return Ok(());
+}
@@ -0,0 +1,282 @@
+use skyrim::{Catalog, EffectSlot, MagicEffect};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    Skyrim,
+    Dawnguard,
+    Hearthfire,
+    Dragonborn
+}
+
+use self::Source::{Skyrim, Dawnguard, Hearthfire, Dragonborn};
+
+// Magnitude and duration are the base values at 0 skill and without perks.
+#[derive(Debug, Clone)]
+pub struct EffectData {
+    pub name: &'static str,
+    pub cost: f64,
+    pub magnitude: f64,
+    pub duration: u32,
+    pub harmful: bool
+}
+
+#[derive(Debug, Clone)]
+pub struct IngredientData {
+    pub name: &'static str,
+    pub value: u32,
+    pub source: Source,
+    pub effects: [&'static str; 4]
+}
+
+impl IngredientData {
+    pub fn effect(&self, slot: EffectSlot) -> &'static EffectData {
+        return effect(self.effects[slot.index()]).expect("Ingredient effects must be in EFFECTS.");
+    }
+}
+
+pub fn effect(name: &str) -> Option<&'static EffectData> {
+    return EFFECTS.iter().find(|data| data.name == name);
+}
+
+pub fn ingredient(name: &str) -> Option<&'static IngredientData> {
+    return INGREDIENTS.iter().find(|data| data.name == name);
+}
+
+// Every effect in the game is a possible value, even if none of the chosen ingredients have it.
+pub fn catalog(names: &[&str]) -> Result<Catalog, String> {
+    let mut ingredients: Vec<String> = Vec::new();
+    for &name in names.iter() {
+        match ingredient(name) {
+            Some(data) => ingredients.push(data.name.to_string()),
+            None => return Err(format!("Unknown ingredient: {}", name))
+        }
+    }
+    let effects = EFFECTS.iter().map(|data| data.name.to_string()).collect();
+    return Ok(Catalog::new(ingredients, effects));
+}
+
+pub fn full_catalog(sources: &[Source]) -> Catalog {
+    let names: Vec<&str> = INGREDIENTS.iter()
+        .filter(|data| sources.contains(&data.source))
+        .map(|data| data.name)
+        .collect();
+    return catalog(&names).expect("INGREDIENTS names are always known.");
+}
+
+// The real effect of every slot in the catalog, indexed like the optimizer's variables.
+pub fn ground_truth(catalog: &Catalog) -> Option<Vec<MagicEffect>> {
+    let mut result: Vec<MagicEffect> = Vec::new();
+    for id in catalog.ingredients() {
+        let data = ingredient(catalog.ingredient_name(id))?;
+        for slot in EffectSlot::all() {
+            match catalog.effect(data.effect(slot).name) {
+                Some(effect) => result.push(effect),
+                None => return None
+            }
+        }
+    }
+    return Some(result);
+}
+
+pub static EFFECTS: &[EffectData] = &[
+    EffectData{ name: "Cure Disease", cost: 0.5, magnitude: 5.0, duration: 0, harmful: false },
+    EffectData{ name: "Damage Health", cost: 3.0, magnitude: 2.0, duration: 1, harmful: true },
+    EffectData{ name: "Damage Magicka", cost: 2.2, magnitude: 3.0, duration: 0, harmful: true },
+    EffectData{ name: "Damage Magicka Regen", cost: 0.5, magnitude: 100.0, duration: 5, harmful: true },
+    EffectData{ name: "Damage Stamina", cost: 1.8, magnitude: 3.0, duration: 0, harmful: true },
+    EffectData{ name: "Damage Stamina Regen", cost: 0.3, magnitude: 100.0, duration: 5, harmful: true },
+    EffectData{ name: "Fear", cost: 5.0, magnitude: 1.0, duration: 30, harmful: true },
+    EffectData{ name: "Fortify Alteration", cost: 0.2, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Barter", cost: 2.0, magnitude: 1.0, duration: 30, harmful: false },
+    EffectData{ name: "Fortify Block", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Carry Weight", cost: 0.15, magnitude: 4.0, duration: 300, harmful: false },
+    EffectData{ name: "Fortify Conjuration", cost: 0.25, magnitude: 5.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Destruction", cost: 0.5, magnitude: 5.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Enchanting", cost: 0.6, magnitude: 1.0, duration: 30, harmful: false },
+    EffectData{ name: "Fortify Health", cost: 0.35, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Heavy Armor", cost: 0.5, magnitude: 2.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Illusion", cost: 0.4, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Light Armor", cost: 0.5, magnitude: 2.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Lockpicking", cost: 0.5, magnitude: 2.0, duration: 30, harmful: false },
+    EffectData{ name: "Fortify Magicka", cost: 0.3, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Marksman", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify One-handed", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Pickpocket", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Restoration", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Smithing", cost: 0.6, magnitude: 4.0, duration: 30, harmful: false },
+    EffectData{ name: "Fortify Sneak", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Stamina", cost: 0.3, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Fortify Two-handed", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Frenzy", cost: 15.0, magnitude: 1.0, duration: 10, harmful: true },
+    EffectData{ name: "Invisibility", cost: 100.0, magnitude: 0.0, duration: 4, harmful: false },
+    EffectData{ name: "Lingering Damage Health", cost: 12.0, magnitude: 1.0, duration: 10, harmful: true },
+    EffectData{ name: "Lingering Damage Magicka", cost: 10.0, magnitude: 1.0, duration: 10, harmful: true },
+    EffectData{ name: "Lingering Damage Stamina", cost: 1.8, magnitude: 1.0, duration: 10, harmful: true },
+    EffectData{ name: "Paralysis", cost: 500.0, magnitude: 0.0, duration: 1, harmful: true },
+    EffectData{ name: "Ravage Health", cost: 0.4, magnitude: 2.0, duration: 10, harmful: true },
+    EffectData{ name: "Ravage Magicka", cost: 1.0, magnitude: 2.0, duration: 10, harmful: true },
+    EffectData{ name: "Ravage Stamina", cost: 1.6, magnitude: 2.0, duration: 10, harmful: true },
+    EffectData{ name: "Regenerate Health", cost: 0.1, magnitude: 5.0, duration: 300, harmful: false },
+    EffectData{ name: "Regenerate Magicka", cost: 0.1, magnitude: 5.0, duration: 300, harmful: false },
+    EffectData{ name: "Regenerate Stamina", cost: 0.1, magnitude: 5.0, duration: 300, harmful: false },
+    EffectData{ name: "Resist Fire", cost: 0.5, magnitude: 3.0, duration: 60, harmful: false },
+    EffectData{ name: "Resist Frost", cost: 0.5, magnitude: 3.0, duration: 60, harmful: false },
+    EffectData{ name: "Resist Magic", cost: 1.0, magnitude: 1.0, duration: 60, harmful: false },
+    EffectData{ name: "Resist Poison", cost: 0.5, magnitude: 4.0, duration: 60, harmful: false },
+    EffectData{ name: "Resist Shock", cost: 0.5, magnitude: 3.0, duration: 60, harmful: false },
+    EffectData{ name: "Restore Health", cost: 0.5, magnitude: 5.0, duration: 0, harmful: false },
+    EffectData{ name: "Restore Magicka", cost: 0.6, magnitude: 5.0, duration: 0, harmful: false },
+    EffectData{ name: "Restore Stamina", cost: 0.6, magnitude: 5.0, duration: 0, harmful: false },
+    EffectData{ name: "Slow", cost: 1.0, magnitude: 50.0, duration: 5, harmful: true },
+    EffectData{ name: "Waterbreathing", cost: 5.0, magnitude: 0.0, duration: 5, harmful: false },
+    EffectData{ name: "Weakness to Fire", cost: 0.6, magnitude: 2.0, duration: 30, harmful: true },
+    EffectData{ name: "Weakness to Frost", cost: 0.5, magnitude: 2.0, duration: 30, harmful: true },
+    EffectData{ name: "Weakness to Magic", cost: 1.0, magnitude: 2.0, duration: 30, harmful: true },
+    EffectData{ name: "Weakness to Poison", cost: 1.0, magnitude: 2.0, duration: 30, harmful: true },
+    EffectData{ name: "Weakness to Shock", cost: 0.7, magnitude: 2.0, duration: 30, harmful: true }
+];
+
+pub static INGREDIENTS: &[IngredientData] = &[
+    IngredientData{ name: "Abecean Longfin", value: 15, source: Skyrim, effects: ["Weakness to Frost", "Fortify Sneak", "Weakness to Poison", "Fortify Restoration"] },
+    IngredientData{ name: "Bear Claws", value: 2, source: Skyrim, effects: ["Restore Stamina", "Fortify Health", "Fortify One-handed", "Damage Magicka Regen"] },
+    IngredientData{ name: "Bee", value: 3, source: Skyrim, effects: ["Restore Stamina", "Ravage Stamina", "Regenerate Stamina", "Weakness to Shock"] },
+    IngredientData{ name: "Beehive Husk", value: 5, source: Skyrim, effects: ["Resist Poison", "Fortify Light Armor", "Fortify Sneak", "Fortify Destruction"] },
+    IngredientData{ name: "Berit's Ashes", value: 0, source: Skyrim, effects: ["Damage Stamina", "Resist Fire", "Fortify Conjuration", "Ravage Stamina"] },
+    IngredientData{ name: "Bleeding Crown", value: 10, source: Skyrim, effects: ["Weakness to Fire", "Fortify Block", "Weakness to Poison", "Resist Magic"] },
+    IngredientData{ name: "Blisterwort", value: 2, source: Skyrim, effects: ["Damage Stamina", "Frenzy", "Restore Health", "Fortify Smithing"] },
+    IngredientData{ name: "Blue Butterfly Wing", value: 2, source: Skyrim, effects: ["Damage Stamina", "Fortify Conjuration", "Damage Magicka Regen", "Fortify Enchanting"] },
+    IngredientData{ name: "Blue Dartwing", value: 1, source: Skyrim, effects: ["Resist Shock", "Fortify Pickpocket", "Restore Health", "Fear"] },
+    IngredientData{ name: "Blue Mountain Flower", value: 2, source: Skyrim, effects: ["Restore Health", "Fortify Conjuration", "Fortify Health", "Damage Magicka Regen"] },
+    IngredientData{ name: "Bone Meal", value: 5, source: Skyrim, effects: ["Damage Stamina", "Resist Fire", "Fortify Conjuration", "Ravage Stamina"] },
+    IngredientData{ name: "Briar Heart", value: 20, source: Skyrim, effects: ["Restore Magicka", "Fortify Block", "Paralysis", "Fortify Magicka"] },
+    IngredientData{ name: "Butterfly Wing", value: 3, source: Skyrim, effects: ["Restore Health", "Fortify Barter", "Lingering Damage Stamina", "Damage Magicka"] },
+    IngredientData{ name: "Canis Root", value: 5, source: Skyrim, effects: ["Damage Stamina", "Fortify One-handed", "Fortify Marksman", "Paralysis"] },
+    IngredientData{ name: "Charred Skeever Hide", value: 1, source: Skyrim, effects: ["Restore Stamina", "Cure Disease", "Resist Poison", "Restore Health"] },
+    IngredientData{ name: "Chaurus Eggs", value: 10, source: Skyrim, effects: ["Weakness to Poison", "Fortify Stamina", "Damage Magicka", "Invisibility"] },
+    IngredientData{ name: "Chicken's Egg", value: 2, source: Skyrim, effects: ["Resist Magic", "Damage Magicka Regen", "Waterbreathing", "Lingering Damage Stamina"] },
+    IngredientData{ name: "Creep Cluster", value: 1, source: Skyrim, effects: ["Restore Magicka", "Damage Stamina Regen", "Fortify Carry Weight", "Weakness to Magic"] },
+    IngredientData{ name: "Crimson Nirnroot", value: 10, source: Skyrim, effects: ["Damage Health", "Damage Stamina", "Invisibility", "Resist Magic"] },
+    IngredientData{ name: "Cyrodilic Spadetail", value: 15, source: Skyrim, effects: ["Damage Stamina", "Fortify Restoration", "Fear", "Ravage Health"] },
+    IngredientData{ name: "Daedra Heart", value: 250, source: Skyrim, effects: ["Restore Health", "Damage Stamina Regen", "Damage Magicka", "Fear"] },
+    IngredientData{ name: "Deathbell", value: 4, source: Skyrim, effects: ["Damage Health", "Ravage Stamina", "Slow", "Weakness to Poison"] },
+    IngredientData{ name: "Dragon's Tongue", value: 5, source: Skyrim, effects: ["Resist Fire", "Fortify Barter", "Fortify Illusion", "Fortify Two-handed"] },
+    IngredientData{ name: "Dwarven Oil", value: 15, source: Skyrim, effects: ["Weakness to Magic", "Fortify Illusion", "Regenerate Magicka", "Restore Magicka"] },
+    IngredientData{ name: "Ectoplasm", value: 25, source: Skyrim, effects: ["Restore Magicka", "Fortify Destruction", "Fortify Magicka", "Damage Health"] },
+    IngredientData{ name: "Elves Ear", value: 10, source: Skyrim, effects: ["Restore Magicka", "Fortify Marksman", "Weakness to Frost", "Resist Fire"] },
+    IngredientData{ name: "Eye of Sabre Cat", value: 2, source: Skyrim, effects: ["Restore Stamina", "Ravage Health", "Damage Magicka", "Restore Health"] },
+    IngredientData{ name: "Falmer Ear", value: 10, source: Skyrim, effects: ["Damage Health", "Frenzy", "Resist Poison", "Fortify Lockpicking"] },
+    IngredientData{ name: "Fire Salts", value: 50, source: Skyrim, effects: ["Weakness to Frost", "Resist Fire", "Restore Magicka", "Regenerate Magicka"] },
+    IngredientData{ name: "Fly Amanita", value: 2, source: Skyrim, effects: ["Resist Fire", "Fortify Two-handed", "Frenzy", "Regenerate Stamina"] },
+    IngredientData{ name: "Frost Mirriam", value: 1, source: Skyrim, effects: ["Resist Frost", "Fortify Sneak", "Ravage Magicka", "Damage Stamina Regen"] },
+    IngredientData{ name: "Frost Salts", value: 100, source: Skyrim, effects: ["Weakness to Fire", "Resist Frost", "Restore Magicka", "Fortify Conjuration"] },
+    IngredientData{ name: "Garlic", value: 1, source: Skyrim, effects: ["Resist Poison", "Fortify Stamina", "Regenerate Magicka", "Regenerate Health"] },
+    IngredientData{ name: "Giant Lichen", value: 5, source: Skyrim, effects: ["Weakness to Shock", "Ravage Health", "Weakness to Poison", "Restore Magicka"] },
+    IngredientData{ name: "Giant's Toe", value: 20, source: Skyrim, effects: ["Damage Stamina", "Fortify Health", "Fortify Carry Weight", "Damage Stamina Regen"] },
+    IngredientData{ name: "Glow Dust", value: 20, source: Skyrim, effects: ["Damage Magicka", "Damage Magicka Regen", "Fortify Destruction", "Resist Shock"] },
+    IngredientData{ name: "Glowing Mushroom", value: 5, source: Skyrim, effects: ["Resist Shock", "Fortify Destruction", "Fortify Smithing", "Fortify Health"] },
+    IngredientData{ name: "Grass Pod", value: 1, source: Skyrim, effects: ["Resist Poison", "Ravage Magicka", "Fortify Alteration", "Restore Magicka"] },
+    IngredientData{ name: "Hagraven Claw", value: 20, source: Skyrim, effects: ["Resist Magic", "Lingering Damage Magicka", "Fortify Enchanting", "Fortify Barter"] },
+    IngredientData{ name: "Hagraven Feathers", value: 20, source: Skyrim, effects: ["Damage Magicka", "Fortify Conjuration", "Frenzy", "Weakness to Shock"] },
+    IngredientData{ name: "Hanging Moss", value: 1, source: Skyrim, effects: ["Damage Magicka", "Fortify Health", "Damage Magicka Regen", "Fortify One-handed"] },
+    IngredientData{ name: "Hawk Beak", value: 15, source: Skyrim, effects: ["Restore Stamina", "Resist Frost", "Fortify Carry Weight", "Resist Shock"] },
+    IngredientData{ name: "Hawk Feathers", value: 15, source: Skyrim, effects: ["Cure Disease", "Fortify Light Armor", "Fortify One-handed", "Fortify Sneak"] },
+    IngredientData{ name: "Histcarp", value: 6, source: Skyrim, effects: ["Restore Stamina", "Fortify Magicka", "Damage Stamina Regen", "Waterbreathing"] },
+    IngredientData{ name: "Honeycomb", value: 5, source: Skyrim, effects: ["Restore Stamina", "Fortify Block", "Fortify Light Armor", "Ravage Stamina"] },
+    IngredientData{ name: "Human Flesh", value: 1, source: Skyrim, effects: ["Damage Health", "Paralysis", "Restore Magicka", "Fortify Sneak"] },
+    IngredientData{ name: "Human Heart", value: 0, source: Skyrim, effects: ["Damage Health", "Damage Magicka", "Damage Magicka Regen", "Frenzy"] },
+    IngredientData{ name: "Ice Wraith Teeth", value: 30, source: Skyrim, effects: ["Weakness to Frost", "Fortify Heavy Armor", "Invisibility", "Weakness to Fire"] },
+    IngredientData{ name: "Imp Stool", value: 0, source: Skyrim, effects: ["Damage Health", "Lingering Damage Health", "Paralysis", "Restore Health"] },
+    IngredientData{ name: "Jarrin Root", value: 10, source: Skyrim, effects: ["Damage Health", "Damage Magicka", "Damage Stamina", "Damage Magicka Regen"] },
+    IngredientData{ name: "Jazbay Grapes", value: 1, source: Skyrim, effects: ["Weakness to Magic", "Fortify Magicka", "Regenerate Magicka", "Ravage Health"] },
+    IngredientData{ name: "Juniper Berries", value: 1, source: Skyrim, effects: ["Weakness to Fire", "Fortify Marksman", "Regenerate Health", "Damage Stamina Regen"] },
+    IngredientData{ name: "Large Antlers", value: 2, source: Skyrim, effects: ["Restore Stamina", "Fortify Stamina", "Slow", "Damage Stamina Regen"] },
+    IngredientData{ name: "Lavender", value: 1, source: Skyrim, effects: ["Resist Magic", "Fortify Stamina", "Ravage Magicka", "Fortify Conjuration"] },
+    IngredientData{ name: "Luna Moth Wing", value: 5, source: Skyrim, effects: ["Damage Magicka", "Fortify Light Armor", "Regenerate Health", "Invisibility"] },
+    IngredientData{ name: "Moon Sugar", value: 50, source: Skyrim, effects: ["Weakness to Fire", "Resist Frost", "Restore Magicka", "Regenerate Magicka"] },
+    IngredientData{ name: "Mora Tapinella", value: 4, source: Skyrim, effects: ["Restore Magicka", "Lingering Damage Health", "Regenerate Stamina", "Fortify Illusion"] },
+    IngredientData{ name: "Mudcrab Chitin", value: 2, source: Skyrim, effects: ["Restore Stamina", "Cure Disease", "Resist Poison", "Resist Fire"] },
+    IngredientData{ name: "Namira's Rot", value: 0, source: Skyrim, effects: ["Damage Magicka", "Fortify Lockpicking", "Fear", "Regenerate Health"] },
+    IngredientData{ name: "Nightshade", value: 8, source: Skyrim, effects: ["Damage Health", "Damage Magicka Regen", "Lingering Damage Stamina", "Fortify Destruction"] },
+    IngredientData{ name: "Nirnroot", value: 10, source: Skyrim, effects: ["Damage Health", "Damage Stamina", "Invisibility", "Resist Magic"] },
+    IngredientData{ name: "Nordic Barnacle", value: 5, source: Skyrim, effects: ["Damage Magicka", "Waterbreathing", "Regenerate Health", "Fortify Pickpocket"] },
+    IngredientData{ name: "Orange Dartwing", value: 1, source: Skyrim, effects: ["Restore Stamina", "Ravage Magicka", "Fortify Pickpocket", "Damage Health"] },
+    IngredientData{ name: "Pine Thrush Egg", value: 2, source: Skyrim, effects: ["Restore Stamina", "Fortify Lockpicking", "Weakness to Poison", "Resist Shock"] },
+    IngredientData{ name: "Powdered Mammoth Tusk", value: 20, source: Skyrim, effects: ["Restore Stamina", "Fortify Sneak", "Weakness to Fire", "Fear"] },
+    IngredientData{ name: "Purple Mountain Flower", value: 2, source: Skyrim, effects: ["Restore Stamina", "Fortify Sneak", "Lingering Damage Magicka", "Resist Frost"] },
+    IngredientData{ name: "Red Mountain Flower", value: 2, source: Skyrim, effects: ["Restore Magicka", "Ravage Magicka", "Fortify Magicka", "Damage Health"] },
+    IngredientData{ name: "River Betty", value: 15, source: Skyrim, effects: ["Damage Health", "Fortify Alteration", "Slow", "Fortify Carry Weight"] },
+    IngredientData{ name: "Rock Warbler Egg", value: 2, source: Skyrim, effects: ["Restore Health", "Fortify One-handed", "Damage Stamina", "Weakness to Magic"] },
+    IngredientData{ name: "Sabre Cat Tooth", value: 2, source: Skyrim, effects: ["Restore Stamina", "Fortify Heavy Armor", "Fortify Smithing", "Weakness to Poison"] },
+    IngredientData{ name: "Salt Pile", value: 2, source: Skyrim, effects: ["Weakness to Magic", "Fortify Restoration", "Slow", "Regenerate Magicka"] },
+    IngredientData{ name: "Scaly Pholiota", value: 4, source: Skyrim, effects: ["Weakness to Magic", "Fortify Illusion", "Regenerate Stamina", "Fortify Carry Weight"] },
+    IngredientData{ name: "Silverside Perch", value: 15, source: Skyrim, effects: ["Restore Stamina", "Damage Stamina Regen", "Ravage Health", "Resist Frost"] },
+    IngredientData{ name: "Skeever Tail", value: 3, source: Skyrim, effects: ["Damage Stamina Regen", "Ravage Health", "Damage Health", "Fortify Light Armor"] },
+    IngredientData{ name: "Slaughterfish Egg", value: 3, source: Skyrim, effects: ["Resist Poison", "Fortify Pickpocket", "Lingering Damage Health", "Fortify Stamina"] },
+    IngredientData{ name: "Slaughterfish Scales", value: 3, source: Skyrim, effects: ["Resist Frost", "Lingering Damage Health", "Fortify Heavy Armor", "Fortify Block"] },
+    IngredientData{ name: "Small Antlers", value: 2, source: Skyrim, effects: ["Weakness to Poison", "Fortify Restoration", "Lingering Damage Stamina", "Damage Health"] },
+    IngredientData{ name: "Snowberries", value: 4, source: Skyrim, effects: ["Resist Fire", "Fortify Enchanting", "Resist Frost", "Resist Shock"] },
+    IngredientData{ name: "Spider Egg", value: 5, source: Skyrim, effects: ["Damage Stamina", "Damage Magicka Regen", "Fortify Lockpicking", "Fortify Marksman"] },
+    IngredientData{ name: "Spriggan Sap", value: 15, source: Skyrim, effects: ["Damage Magicka Regen", "Fortify Enchanting", "Fortify Smithing", "Fortify Alteration"] },
+    IngredientData{ name: "Swamp Fungal Pod", value: 3, source: Skyrim, effects: ["Resist Shock", "Lingering Damage Magicka", "Paralysis", "Restore Health"] },
+    IngredientData{ name: "Taproot", value: 15, source: Skyrim, effects: ["Weakness to Magic", "Fortify Illusion", "Regenerate Magicka", "Restore Magicka"] },
+    IngredientData{ name: "Thistle Branch", value: 1, source: Skyrim, effects: ["Resist Frost", "Ravage Stamina", "Resist Poison", "Fortify Heavy Armor"] },
+    IngredientData{ name: "Torchbug Thorax", value: 1, source: Skyrim, effects: ["Restore Stamina", "Lingering Damage Magicka", "Weakness to Magic", "Fortify Stamina"] },
+    IngredientData{ name: "Troll Fat", value: 15, source: Skyrim, effects: ["Resist Poison", "Fortify Two-handed", "Frenzy", "Damage Health"] },
+    IngredientData{ name: "Tundra Cotton", value: 1, source: Skyrim, effects: ["Resist Magic", "Fortify Magicka", "Fortify Block", "Fortify Barter"] },
+    IngredientData{ name: "Vampire Dust", value: 25, source: Skyrim, effects: ["Invisibility", "Restore Magicka", "Regenerate Health", "Cure Disease"] },
+    IngredientData{ name: "Void Salts", value: 125, source: Skyrim, effects: ["Weakness to Shock", "Resist Magic", "Damage Health", "Fortify Magicka"] },
+    IngredientData{ name: "Wheat", value: 5, source: Skyrim, effects: ["Restore Health", "Fortify Health", "Damage Stamina Regen", "Lingering Damage Magicka"] },
+    IngredientData{ name: "White Cap", value: 0, source: Skyrim, effects: ["Weakness to Frost", "Fortify Heavy Armor", "Restore Magicka", "Ravage Magicka"] },
+    IngredientData{ name: "Wisp Wrappings", value: 2, source: Skyrim, effects: ["Restore Stamina", "Fortify Destruction", "Fortify Carry Weight", "Resist Magic"] },
+    IngredientData{ name: "Ancestor Moth Wing", value: 2, source: Dawnguard, effects: ["Damage Stamina", "Fortify Conjuration", "Damage Magicka Regen", "Fortify Enchanting"] },
+    IngredientData{ name: "Chaurus Hunter Antennae", value: 2, source: Dawnguard, effects: ["Damage Stamina", "Fortify Conjuration", "Damage Magicka Regen", "Fortify Enchanting"] },
+    IngredientData{ name: "Gleamblossom", value: 5, source: Dawnguard, effects: ["Resist Magic", "Fear", "Regenerate Health", "Paralysis"] },
+    IngredientData{ name: "Poison Bloom", value: 5, source: Dawnguard, effects: ["Damage Health", "Slow", "Fortify Carry Weight", "Fear"] },
+    IngredientData{ name: "Yellow Mountain Flower", value: 2, source: Dawnguard, effects: ["Resist Poison", "Fortify Restoration", "Fortify Health", "Damage Stamina Regen"] },
+    IngredientData{ name: "Hawk's Egg", value: 5, source: Hearthfire, effects: ["Resist Magic", "Damage Magicka Regen", "Waterbreathing", "Lingering Damage Stamina"] },
+    IngredientData{ name: "Salmon Roe", value: 5, source: Hearthfire, effects: ["Restore Stamina", "Waterbreathing", "Fortify Magicka", "Regenerate Magicka"] },
+    IngredientData{ name: "Ash Creep Cluster", value: 20, source: Dragonborn, effects: ["Damage Stamina", "Invisibility", "Resist Fire", "Fortify Destruction"] },
+    IngredientData{ name: "Ash Hopper Jelly", value: 20, source: Dragonborn, effects: ["Restore Health", "Fortify Light Armor", "Resist Shock", "Frenzy"] },
+    IngredientData{ name: "Boar Tusk", value: 20, source: Dragonborn, effects: ["Fortify Stamina", "Fortify Health", "Fortify Block", "Frenzy"] },
+    IngredientData{ name: "Burnt Spriggan Wood", value: 20, source: Dragonborn, effects: ["Weakness to Fire", "Fortify Alteration", "Damage Magicka Regen", "Slow"] },
+    IngredientData{ name: "Emperor Parasol Moss", value: 25, source: Dragonborn, effects: ["Damage Health", "Fortify Magicka", "Regenerate Health", "Fortify Two-handed"] },
+    IngredientData{ name: "Felsaad Tern Feathers", value: 15, source: Dragonborn, effects: ["Restore Health", "Fortify Light Armor", "Cure Disease", "Resist Magic"] },
+    IngredientData{ name: "Netch Jelly", value: 20, source: Dragonborn, effects: ["Paralysis", "Fortify Carry Weight", "Restore Stamina", "Fear"] },
+    IngredientData{ name: "Pearl", value: 2, source: Dragonborn, effects: ["Restore Stamina", "Restore Magicka", "Resist Shock", "Fortify Block"] },
+    IngredientData{ name: "Scathecraw", value: 20, source: Dragonborn, effects: ["Ravage Health", "Ravage Stamina", "Ravage Magicka", "Lingering Damage Health"] },
+    IngredientData{ name: "Small Pearl", value: 2, source: Dragonborn, effects: ["Restore Stamina", "Fortify One-handed", "Fortify Restoration", "Resist Frost"] },
+    IngredientData{ name: "Spawn Ash", value: 20, source: Dragonborn, effects: ["Ravage Stamina", "Resist Fire", "Fortify Enchanting", "Ravage Magicka"] },
+    IngredientData{ name: "Trama Root", value: 20, source: Dragonborn, effects: ["Weakness to Shock", "Fortify Carry Weight", "Damage Magicka", "Slow"] }
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn every_ingredient_has_four_known_effects() {
+        for data in INGREDIENTS.iter() {
+            let effects: HashSet<&str> = EffectSlot::all().into_iter().map(|slot| data.effect(slot).name).collect();
+            assert_eq!(effects.len(), 4, "{}", data.name);
+        }
+        let names: HashSet<&str> = INGREDIENTS.iter().map(|data| data.name).collect();
+        assert_eq!(names.len(), INGREDIENTS.len());
+        let effects: HashSet<&str> = EFFECTS.iter().map(|data| data.name).collect();
+        assert_eq!(effects.len(), EFFECTS.len());
+    }
+
+    #[test]
+    fn catalogs_only_know_bundled_ingredients() {
+        assert!(catalog(&["Wheat", "Sweetroll"]).is_err());
+        let catalog = catalog(&["Wheat"]).unwrap();
+        assert_eq!(catalog.k(), EFFECTS.len());
+        assert_eq!(catalog.varc(), 4);
+
+        let vanilla = full_catalog(&[Skyrim]).ingredients().len();
+        let everything = full_catalog(&[Skyrim, Dawnguard, Hearthfire, Dragonborn]).ingredients().len();
+        assert!(vanilla < everything);
+        assert_eq!(everything, INGREDIENTS.len());
+        assert!(ground_truth(&full_catalog(&[Skyrim, Dawnguard, Hearthfire, Dragonborn])).is_some());
+    }
+}
@@ -0,0 +1,288 @@
+use alchemy::{MULT, VariableType};
+use alchemy::VariableType::{BaseVariable, Lagrangian, EquivalentSums, CountLagrangian};
+use gradient_descent::{FRACTION_TO_BOUNDARY, Gradient, Operator, Options, Solution};
+use gradient_descent;
+use nalgebra::DVector;
+use std::collections::BTreeMap;
+
+type DynVector = DVector<f64>;
+
+// For fixed multipliers the cells that maximize the Lagrangian are
+//     count * e^(-1 - (sum of coefficient * multiplier over the rows the cell is in) / MULT),
+// so the multipliers alone describe a point, and minimizing the dual over them leaves every row
+// at its target. Newton does that for all the multipliers at once, and Scaling for one at a time.
+// A row that only has to reach its target instead has a multiplier of at most zero, which is
+// zero unless the row is exactly at its target.
+
+// The barrier on the multipliers of inequalities starts here, and shrinks each time Newton
+// gets within ten times it until it is below the square of the tolerance.
+const BARRIER: f64 = 1e-2;
+const MIN_BARRIER: f64 = 1e-30;
+
+// One linear constraint: the weighted sum of cells hits the target, or with at_least reaches it.
+// Indices are into the variables of the problem.
+pub struct Row {
+    pub lagrangian: usize,
+    pub cells: Vec<(usize, f64)>,
+    pub target: f64,
+    pub at_least: bool
+}
+
+impl Row {
+    pub fn residual(&self, x: &DynVector) -> f64 {
+        let cells = x.as_slice();
+        let total: f64 = self.cells.iter().map(|&(i, coefficient)| coefficient * cells[i]).sum();
+        return total - self.target;
+    }
+}
+
+// A cell listed twice in a sum counts twice, and cells on both sides of a sum cancel.
+fn coefficients(positive: &[usize], negative: &[usize]) -> Vec<(usize, f64)> {
+    let mut result: BTreeMap<usize, f64> = BTreeMap::new();
+    for &i in positive.iter() {
+        *result.entry(i).or_insert(0.0) += 1.0;
+    }
+    for &i in negative.iter() {
+        *result.entry(i).or_insert(0.0) -= 1.0;
+    }
+    return result.into_iter().filter(|&(_, coefficient)| coefficient != 0.0).collect();
+}
+
+pub fn rows(var_meaning: &[VariableType]) -> Vec<Row> {
+    let mut result: Vec<Row> = Vec::new();
+    for (i, meaning) in var_meaning.iter().enumerate() {
+        match *meaning {
+            Lagrangian(ref cells) => {
+                result.push(Row{ lagrangian: i, cells: coefficients(cells, &[]), target: 1.0, at_least: false });
+            },
+            EquivalentSums(ref to_add, ref to_min) => {
+                result.push(Row{ lagrangian: i, cells: coefficients(to_add, to_min), target: 0.0, at_least: false });
+            },
+            CountLagrangian{ ref sum, target, at_least } => {
+                result.push(Row{ lagrangian: i, cells: coefficients(sum, &[]), target, at_least });
+            },
+            BaseVariable{ .. } => {}
+        }
+    }
+    return result;
+}
+
+struct Cell {
+    var: usize,
+    count: f64,
+    // The rows the cell is in, by their position in rows, with its coefficient in each.
+    rows: Vec<(usize, f64)>
+}
+
+// The dual as a function of the multipliers, in the order of rows. Inequalities add
+// -barrier * ln(-multiplier), which keeps their multipliers below zero and lets each row
+// fall short of its target by barrier / -multiplier.
+pub struct EntropyDual {
+    size: usize,
+    rows: Vec<Row>,
+    cells: Vec<Cell>,
+    barrier: f64
+}
+
+impl EntropyDual {
+    pub fn new(var_meaning: &[VariableType]) -> EntropyDual {
+        let rows = rows(var_meaning);
+        let mut cells: Vec<Cell> = Vec::new();
+        let mut position: Vec<Option<usize>> = vec![None; var_meaning.len()];
+        for (i, meaning) in var_meaning.iter().enumerate() {
+            if let BaseVariable{ count, .. } = *meaning {
+                position[i] = Some(cells.len());
+                cells.push(Cell{ var: i, count, rows: Vec::new() });
+            }
+        }
+        for (j, row) in rows.iter().enumerate() {
+            for &(i, coefficient) in row.cells.iter() {
+                let cell = position[i].expect("Rows only sum over cells.");
+                cells[cell].rows.push((j, coefficient));
+            }
+        }
+        return EntropyDual{ size: var_meaning.len(), rows, cells, barrier: 0.0 };
+    }
+
+    // The multipliers of a point of the problem.
+    pub fn multipliers(&self, x: &DynVector) -> DynVector {
+        return DynVector::from_iterator(self.rows.len(), self.rows.iter().map(|row| x[row.lagrangian]));
+    }
+
+    // The point of the problem the multipliers describe.
+    pub fn primal(&self, y: &DynVector) -> DynVector {
+        let mut x = DynVector::from_element(self.size, 0.0);
+        for (cell, value) in self.cells.iter().zip(self.cell_values(y)) {
+            x[cell.var] = value;
+        }
+        for (j, row) in self.rows.iter().enumerate() {
+            x[row.lagrangian] = y[j];
+        }
+        return x;
+    }
+
+    // How far the barrier lets row j fall short of its target.
+    fn slack(&self, y: &DynVector, j: usize) -> f64 {
+        return if self.rows[j].at_least { self.barrier / -y[j] } else { 0.0 };
+    }
+
+    fn cell_values(&self, y: &DynVector) -> Vec<f64> {
+        let y = y.as_slice();
+        return self.cells.iter()
+            .map(|cell| {
+                let sum: f64 = cell.rows.iter().map(|&(j, coefficient)| coefficient * y[j]).sum();
+                cell.count * (-1.0 - sum / MULT).exp()
+            })
+            .collect();
+    }
+}
+
+// The Hessian of the dual is A diag(cells / MULT) A^T for the matrix A of the rows, plus the
+// curvature of the barrier. It is kept as A and the weights, since the product is much denser.
+struct NormalMatrix<'a> {
+    dual: &'a EntropyDual,
+    weights: Vec<f64>,
+    diagonal: Vec<f64>
+}
+
+impl<'a> Operator for NormalMatrix<'a> {
+    fn mul(&self, v: &DynVector) -> DynVector {
+        let v = v.as_slice();
+        let mut result: Vec<f64> = self.diagonal.iter().zip(v.iter()).map(|(&d, &value)| d * value).collect();
+        for (cell, &weight) in self.dual.cells.iter().zip(self.weights.iter()) {
+            let along: f64 = cell.rows.iter().map(|&(j, coefficient)| coefficient * v[j]).sum();
+            for &(j, coefficient) in cell.rows.iter() {
+                result[j] += coefficient * weight * along;
+            }
+        }
+        return DynVector::from_vec(result);
+    }
+
+    fn diagonal(&self) -> DynVector {
+        let mut result = self.diagonal.clone();
+        for (cell, &weight) in self.dual.cells.iter().zip(self.weights.iter()) {
+            for &(j, coefficient) in cell.rows.iter() {
+                result[j] += coefficient * coefficient * weight;
+            }
+        }
+        return DynVector::from_vec(result);
+    }
+}
+
+impl Gradient for EntropyDual {
+    // MULT times the sum of the cells, plus target times multiplier over the rows, plus the barrier.
+    fn value(&self, y: &DynVector) -> f64 {
+        let cells: f64 = self.cell_values(y).iter().sum();
+        let rows: f64 = self.rows.iter().enumerate()
+            .map(|(j, row)| {
+                let barrier = if row.at_least { -self.barrier * (-y[j]).ln() } else { 0.0 };
+                row.target * y[j] + barrier
+            })
+            .sum();
+        return MULT * cells + rows;
+    }
+
+    fn gradient(&self, y: &DynVector) -> DynVector {
+        let x = self.primal(y);
+        return DynVector::from_iterator(self.rows.len(), (0..self.rows.len()).map(|j| self.slack(y, j) - self.rows[j].residual(&x)));
+    }
+
+    fn hessian<'a>(&'a self, y: &DynVector) -> Box<dyn Operator + 'a> {
+        let weights = self.cell_values(y).into_iter().map(|value| value / MULT).collect();
+        let diagonal = self.rows.iter().enumerate()
+            .map(|(j, row)| if row.at_least { self.barrier / (y[j] * y[j]) } else { 0.0 })
+            .collect();
+        return Box::new(NormalMatrix{ dual: self, weights, diagonal });
+    }
+
+    // The multipliers of inequalities stay below zero.
+    fn max_step(&self, y: &DynVector, step: &DynVector) -> f64 {
+        let mut result: f64 = 1.0;
+        for (j, row) in self.rows.iter().enumerate() {
+            if row.at_least && step[j] < 0.0 {
+                result = result.min(FRACTION_TO_BOUNDARY * y[j] / step[j]);
+            }
+        }
+        return result;
+    }
+}
+
+// Newton on the dual from the multipliers of start, returning the point they describe. With
+// inequalities it is solved again for each barrier, from where the last one left off, and
+// all of them share max_iter.
+pub fn optimize(var_meaning: &[VariableType], start: &DynVector, options: &Options) -> Result<Solution, String> {
+    let mut dual = EntropyDual::new(var_meaning);
+    let mut y = dual.multipliers(start);
+    let inequalities = dual.rows.iter().any(|row| row.at_least);
+    if inequalities {
+        dual.barrier = BARRIER;
+        for (j, row) in dual.rows.iter().enumerate() {
+            if row.at_least {
+                y[j] = y[j].min(-BARRIER);
+            }
+        }
+    }
+
+    let last = options.absolute_tolerance.powi(2).max(MIN_BARRIER);
+    let mut iterations = 0;
+    loop {
+        let done = !inequalities || dual.barrier <= last;
+        let mut stage = Options{ max_iter: options.max_iter - iterations, ..*options };
+        if !done {
+            stage.absolute_tolerance = stage.absolute_tolerance.max(10.0 * dual.barrier);
+        }
+        let solution = try!(gradient_descent::optimize(&dual, y, &stage));
+        iterations += solution.iterations;
+        if done || !solution.converged() {
+            return Ok(Solution{ x: dual.primal(&solution.x), iterations, ..solution });
+        }
+        // Scaling the multipliers of inequalities with the barrier keeps their slacks where they
+        // were, which is right for those past their target, whose multipliers only get smaller.
+        let barrier = (0.1 * dual.barrier).min(dual.barrier.powf(1.5)).max(last);
+        y = solution.x;
+        for (j, row) in dual.rows.iter().enumerate() {
+            if row.at_least {
+                y[j] *= barrier / dual.barrier;
+            }
+        }
+        dual.barrier = barrier;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solver::VarAndValue;
+
+    fn cell(value1: usize, value2: usize, count: f64) -> VariableType {
+        let var1 = VarAndValue{ var: 0, value: value1 };
+        let var2 = VarAndValue{ var: 1, value: value2 };
+        return BaseVariable{ var1, var2, count, lagrangians: Vec::new(), neg_lags: Vec::new() };
+    }
+
+    #[test]
+    fn gradient_and_hessian_match_the_value() {
+        let var_meaning = vec![
+            cell(0, 1, 1.0),
+            cell(1, 0, 2.0),
+            cell(1, 1, 1.0),
+            Lagrangian(vec![0, 1, 2]),
+            CountLagrangian{ sum: vec![0, 2], target: 0.3, at_least: true }
+        ];
+        let mut dual = EntropyDual::new(&var_meaning);
+        dual.barrier = 1e-3;
+        let y = DynVector::from_vec(vec![0.2, -0.5]);
+        let gradient = dual.gradient(&y);
+        let hessian = dual.hessian(&y);
+        let h = 1e-6;
+        for j in 0..2 {
+            let mut step = DynVector::from_element(2, 0.0);
+            step[j] = h;
+            let slope = (dual.value(&(&y + &step)) - dual.value(&(&y - &step))) / (2.0 * h);
+            assert!((slope - gradient[j]).abs() < 1e-8);
+            let curvature = (dual.gradient(&(&y + &step)) - dual.gradient(&(&y - &step))) / (2.0 * h);
+            let unit = DynVector::from_fn(2, |i, _| if i == j { 1.0 } else { 0.0 });
+            assert!((curvature - hessian.mul(&unit)).norm() < 1e-8);
+        }
+    }
+}
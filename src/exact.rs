@@ -0,0 +1,145 @@
+use alchemy::EntropyOptimizer;
+use gradient_descent::Options;
+use solver::{EntropyConstraint, VarAndValue};
+use std::collections::HashMap;
+
+// The number of assignments grows exponentially, so past this many the search gives up.
+pub const MAX_NODES: usize = 10_000_000;
+
+struct Search<'a> {
+    k: usize,
+    counts: &'a [f64],
+    // The order each variable tries its values in.
+    order: &'a [Vec<usize>],
+    // The constraints on each variable, checked as soon as it is assigned.
+    watching: Vec<Vec<&'a EntropyConstraint>>,
+    assignment: Vec<Option<usize>>,
+    // How many of the values a value stands for are taken so far.
+    used: Vec<usize>,
+    weights: HashMap<VarAndValue, f64>,
+    total: f64,
+    nodes: usize,
+    // Stop at the first consistent assignment rather than visiting them all.
+    first_only: bool,
+    first: Option<Vec<usize>>
+}
+
+impl<'a> Search<'a> {
+    // A value standing for several is split into labels, numbered in order of first use so that
+    // each set of assignments differing only in which of the values they took is visited once.
+    // Label l of value v is written v + k * l, so only label 0 can equal a value a constraint names,
+    // and only the lumped values no constraint names have more than one label.
+    fn search(&mut self, var: usize, weight: f64) -> Result<(), String> {
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return Err(format!("More than {} partial assignments, too many to enumerate.", MAX_NODES));
+        }
+        if var == self.assignment.len() {
+            if self.first_only {
+                self.first = Some(self.assignment.iter().map(|id| id.expect("Every variable is assigned.")).collect());
+                return Ok(());
+            }
+            self.total += weight;
+            for (var, id) in self.assignment.iter().enumerate() {
+                let value = id.expect("Every variable is assigned.") % self.k;
+                *self.weights.entry(VarAndValue{ var, value }).or_insert(0.0) += weight;
+            }
+            return Ok(());
+        }
+
+        let order = self.order;
+        for &value in order[var].iter() {
+            let used = self.used[value];
+            let labels = if (used as f64) < self.counts[value] { used + 1 } else { used };
+            for label in 0..labels {
+                self.assignment[var] = Some(value + self.k * label);
+                if !self.watching[var].iter().all(|contra| contra.satisfied(&self.assignment)) {
+                    continue;
+                }
+                // A new label stands for any of the values not taken yet.
+                if label == used {
+                    self.used[value] += 1;
+                    try!(self.search(var + 1, weight * (self.counts[value] - used as f64)));
+                    self.used[value] -= 1;
+                } else {
+                    try!(self.search(var + 1, weight));
+                }
+                if self.first.is_some() {
+                    return Ok(());
+                }
+            }
+        }
+        self.assignment[var] = None;
+        return Ok(());
+    }
+
+    fn new(optimizer: &'a EntropyOptimizer, counts: &'a [f64], order: &'a [Vec<usize>], first_only: bool) -> Search<'a> {
+        return Search{
+            k: optimizer.k,
+            counts,
+            order,
+            watching: watching(optimizer),
+            assignment: vec![None; optimizer.varc],
+            used: vec![0; optimizer.k],
+            weights: HashMap::new(),
+            total: 0.0,
+            nodes: 0,
+            first_only,
+            first: None
+        };
+    }
+}
+
+// The constraints on each variable.
+pub fn watching(optimizer: &EntropyOptimizer) -> Vec<Vec<&EntropyConstraint>> {
+    let mut result: Vec<Vec<&EntropyConstraint>> = vec![Vec::new(); optimizer.varc];
+    for contra in optimizer.contras.iter() {
+        let mut vars = contra.variables();
+        vars.sort();
+        vars.dedup();
+        for var in vars {
+            result[var].push(contra);
+        }
+    }
+    return result;
+}
+
+// Some assignment satisfying the constraints, with values written as labels like the search does.
+// Which one depends on the order each variable tries its values in.
+pub fn consistent_assignment(optimizer: &EntropyOptimizer, counts: &[f64], order: &[Vec<usize>]) -> Result<Vec<usize>, String> {
+    let mut search = Search::new(optimizer, counts, order, true);
+    try!(search.search(0, 1.0));
+    return search.first.ok_or_else(|| "No assignment satisfies the constraints.".to_string());
+}
+
+pub struct Marginals {
+    pub distribution: HashMap<VarAndValue, f64>,
+    // Whether the weights stayed finite and each variable's marginals sum to one within the
+    // tolerance. Components with many lumped values can overflow the weights.
+    pub converged: bool
+}
+
+// The exact marginals when every assignment satisfying the constraints is equally likely.
+pub fn marginals(optimizer: &EntropyOptimizer, counts: &[f64], options: &Options) -> Result<Marginals, String> {
+    let order: Vec<Vec<usize>> = vec![(0..optimizer.k).collect(); optimizer.varc];
+    let mut search = Search::new(optimizer, counts, &order, false);
+    try!(search.search(0, 1.0));
+    if search.total == 0.0 {
+        return Err("No assignment satisfies the constraints.".to_string());
+    }
+
+    let total = search.total;
+    let mut distribution: HashMap<VarAndValue, f64> = HashMap::new();
+    let mut converged = total.is_finite();
+    for var in 0..optimizer.varc {
+        let mut sum = 0.0;
+        for value in 0..optimizer.k {
+            let varval = VarAndValue{ var, value };
+            let prob = search.weights.get(&varval).cloned().unwrap_or(0.0) / total;
+            distribution.insert(varval, prob);
+            sum += prob;
+        }
+        converged = converged && (sum - 1.0).abs() <= options.absolute_tolerance;
+    }
+    return Ok(Marginals{ distribution, converged });
+}
@@ -1,47 +1,256 @@
-use std::f64;
-use nalgebra::{MatrixN, Dynamic, VectorN};
+use std::cmp;
+use nalgebra::DVector;
 
 pub trait Gradient {
-    fn gradient(&self, x: &VectorN<f64, Dynamic>) -> VectorN<f64, Dynamic>;
-    fn hessian(&self, x: &VectorN<f64, Dynamic>) -> MatrixN<f64, Dynamic>;
+    // The function being minimized, which decides whether a step is taken.
+    fn value(&self, x: &DVector<f64>) -> f64;
+    fn gradient(&self, x: &DVector<f64>) -> DVector<f64>;
+    // The Hessian at x as a linear map, since it is often much denser than what it is built from.
+    fn hessian<'a>(&'a self, x: &DVector<f64>) -> Box<dyn Operator + 'a>;
+
+    // Largest multiple of step that can be taken from x, as in x - rate * step, without
+    // leaving the domain. Unbounded problems can take the full Newton step.
+    fn max_step(&self, _x: &DVector<f64>, _step: &DVector<f64>) -> f64 {
+        return 1.0;
+    }
+}
+
+// A symmetric positive semidefinite matrix.
+pub trait Operator {
+    fn mul(&self, x: &DVector<f64>) -> DVector<f64>;
+    fn diagonal(&self) -> DVector<f64>;
 }
 
-type DynVec = VectorN<f64, Dynamic>;
+type DynVec = DVector<f64>;
+
+// Steps stop this fraction of the way to a bound so variables stay strictly inside.
+pub const FRACTION_TO_BOUNDARY: f64 = 0.995;
+
+// A step must lower the value by this share of what the slope promises to be accepted.
+const SUFFICIENT_DECREASE: f64 = 1e-4;
+const MAX_BACKTRACKS: usize = 50;
+
+// Far from the solution a rough Newton direction does as well as an exact one, so the linear
+// solve only shrinks its residual by min(MAX_FORCING, sqrt(gradient)), and gets more accurate
+// as Newton closes in, which keeps convergence superlinear.
+const MAX_FORCING: f64 = 0.5;
+
+// Redundant constraints, like marginals that are already implied, make the Hessian singular.
+// Adding a little to its diagonal makes it definite and so always solvable, while only nudging
+// the Newton direction. The residual is still the true gradient, so the point converged to is unchanged.
+const DUAL_REGULARIZATION: f64 = 1e-10;
 
-pub fn solve_iter(a: MatrixN<f64, Dynamic>, b: &DynVec) -> DynVec {
-    let mut copy = a.clone();
-    for col in 0..a.nrows() {
-        for row in 0..a.ncols() {
-            copy[(row, col)] += 1e-3;
+// Conjugate gradient, preconditioned with the diagonal, until the residual is tolerance times b.
+pub fn solve_iter(a: &dyn Operator, b: &DynVec, tolerance: f64, max_iter: usize) -> DynVec {
+    let n = b.len();
+    let inverse = a.diagonal().map(|d| 1.0 / (d + DUAL_REGULARIZATION));
+    let regularized = |x: &DynVec| -> DynVec {
+        return a.mul(x) + x * DUAL_REGULARIZATION;
+    };
+
+    let mut x = DynVec::from_element(n, 0.0);
+    let mut r = b.clone();
+    let mut z = r.component_mul(&inverse);
+    let mut p = z.clone();
+    let mut rz = r.dot(&z);
+    let goal = tolerance * b.norm();
+    for _ in 0..max_iter {
+        if r.norm() <= goal {
+            break;
+        }
+        let ap = regularized(&p);
+        let curvature = p.dot(&ap);
+        if curvature.is_nan() || curvature <= 0.0 {
+            break;
         }
+        let alpha = rz / curvature;
+        x += &p * alpha;
+        r -= &ap * alpha;
+        z = r.component_mul(&inverse);
+        let next = r.dot(&z);
+        p = &z + &p * (next / rz);
+        rz = next;
     }
 
-    return if let Some(res) = copy.lu().solve(&b) {
-        res
-    } else {
-        a.svd(true, true).solve(b, 1e-3)
-    };
+    return x;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Options {
+    pub absolute_tolerance: f64, // Stop once the KKT residual is this small,
+    pub relative_tolerance: f64, // or this small compared to where it started,
+    pub step_tolerance: f64, // or a Newton step moves x by less than this relative to x.
+    pub max_iter: usize
 }
 
-pub fn optimize<T: Gradient>(gradient: &T, mut start: VectorN<f64, Dynamic>) -> VectorN<f64, Dynamic> {
-    let rate = 1.0;
-    let mut last_grad = f64::MAX;
-    // TODO: Add in more sophisticated stoping condition.
+impl Default for Options {
+    fn default() -> Options {
+        return Options{
+            absolute_tolerance: 1e-9,
+            relative_tolerance: 1e-12,
+            step_tolerance: 1e-14,
+            max_iter: 100
+        };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    AbsoluteResidual,
+    RelativeResidual,
+    SmallStep,
+    MaxIterations,
+    // No step along the Newton direction lowered the value, so x is the last point that did.
+    LineSearch
+}
+
+pub struct Solution {
+    pub x: DynVec,
+    pub iterations: usize,
+    pub residual: f64,
+    pub reason: StopReason
+}
+
+impl Solution {
+    // A small step only means Newton stalled, not that the constraints hold.
+    pub fn converged(&self) -> bool {
+        return self.reason == StopReason::AbsoluteResidual || self.reason == StopReason::RelativeResidual;
+    }
+}
+
+pub fn optimize<T: Gradient>(gradient: &T, start: DynVec, options: &Options) -> Result<Solution, String> {
+    let mut x = start;
+    let mut initial: Option<f64> = None;
+    let mut iterations = 0;
+    // A small step is only a reason to stop once the residual has been checked after it.
+    let mut small_step = false;
     loop {
-        let mut grad = gradient.gradient(&start);
-        let norm = grad.norm();
-        println!("Gradient norm: {}", norm);
-        if last_grad - norm < 1e-2 {
-            break;
+        let grad = gradient.gradient(&x);
+        let residual = grad.norm();
+        if !residual.is_finite() {
+            return Err(format!("Newton diverged after {} iterations.", iterations));
+        }
+        let initial = *initial.get_or_insert(residual);
+
+        let reason = if residual <= options.absolute_tolerance {
+            Some(StopReason::AbsoluteResidual)
+        } else if residual <= options.relative_tolerance * initial {
+            Some(StopReason::RelativeResidual)
+        } else if small_step {
+            Some(StopReason::SmallStep)
+        } else if iterations >= options.max_iter {
+            Some(StopReason::MaxIterations)
         } else {
-            last_grad = norm;
+            None
+        };
+        if let Some(reason) = reason {
+            return Ok(Solution{ x, iterations, residual, reason });
+        }
+
+        let forcing = MAX_FORCING.min(residual.sqrt());
+        let direction = solve_iter(&*gradient.hessian(&x), &grad, forcing, cmp::max(100, x.len()));
+        let value = gradient.value(&x);
+        let slope = grad.dot(&direction);
+        let mut rate = gradient.max_step(&x, &direction).min(1.0);
+        let mut backtracks = 0;
+        // Near the minimum the change in value is lost to rounding before the gradient is
+        // small, so a step that shrinks the gradient is taken too. A NaN fails both and backtracks.
+        let next = loop {
+            let next = &x - &direction * rate;
+            if value - gradient.value(&next) >= SUFFICIENT_DECREASE * rate * slope ||
+                gradient.gradient(&next).norm() <= (1.0 - SUFFICIENT_DECREASE * rate) * residual {
+                break next;
+            }
+            if backtracks == MAX_BACKTRACKS {
+                return Ok(Solution{ x, iterations, residual, reason: StopReason::LineSearch });
+            }
+            rate /= 2.0;
+            backtracks += 1;
+        };
+
+        let step = (&direction * rate).norm();
+        x = next;
+        iterations += 1;
+        small_step = step <= options.step_tolerance * (1.0 + x.norm());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dense(Vec<Vec<f64>>);
+
+    impl Operator for Dense {
+        fn mul(&self, x: &DynVec) -> DynVec {
+            return DynVec::from_iterator(x.len(), self.0.iter().map(|row| row.iter().zip(x.iter()).map(|(a, b)| a * b).sum()));
+        }
+
+        fn diagonal(&self) -> DynVec {
+            return DynVec::from_iterator(self.0.len(), (0..self.0.len()).map(|i| self.0[i][i]));
+        }
+    }
+
+    // The sum of e^x_i - target_i * x_i, smallest at x_i = ln target_i.
+    struct Exponentials(Vec<f64>);
+
+    impl Gradient for Exponentials {
+        fn value(&self, x: &DynVec) -> f64 {
+            return x.iter().zip(self.0.iter()).map(|(x, target)| x.exp() - target * x).sum();
+        }
+
+        fn gradient(&self, x: &DynVec) -> DynVec {
+            return DynVec::from_iterator(x.len(), x.iter().zip(self.0.iter()).map(|(x, target)| x.exp() - target));
+        }
+
+        fn hessian<'a>(&'a self, x: &DynVec) -> Box<dyn Operator + 'a> {
+            let n = x.len();
+            return Box::new(Dense((0..n).map(|i| (0..n).map(|j| if i == j { x[i].exp() } else { 0.0 }).collect()).collect()));
+        }
+    }
+
+    #[test]
+    fn conjugate_gradient_solves_definite_systems() {
+        let a = Dense(vec![vec![4.0, 1.0, 0.0], vec![1.0, 3.0, -1.0], vec![0.0, -1.0, 2.0]]);
+        let b = DynVec::from_vec(vec![1.0, 2.0, 3.0]);
+        let x = solve_iter(&a, &b, 1e-14, 100);
+        assert!((a.mul(&x) - &b).norm() < 1e-8);
+    }
+
+    #[test]
+    fn newton_converges_to_the_minimum() {
+        let targets = vec![1.0, 2.0, 0.5, 10.0];
+        let solution = optimize(&Exponentials(targets.clone()), DynVec::from_element(4, 0.0), &Options::default()).unwrap();
+        assert!(solution.converged());
+        assert!(solution.iterations < 20);
+        for (x, target) in solution.x.iter().zip(targets.iter()) {
+            assert!((x - target.ln()).abs() < 1e-9);
         }
-        let hess = gradient.hessian(&start);
-        grad = solve_iter(hess, &grad);
-        // let svd = hess.svd(true, true);
-        // grad = svd.solve(&grad, 1e-6);
-        start = start - rate * grad;
     }
 
-    return start;
+    // Newton along the negated gradient only climbs.
+    struct Uphill(Exponentials);
+
+    impl Gradient for Uphill {
+        fn value(&self, x: &DynVec) -> f64 {
+            return self.0.value(x);
+        }
+
+        fn gradient(&self, x: &DynVec) -> DynVec {
+            return -self.0.gradient(x);
+        }
+
+        fn hessian<'a>(&'a self, x: &DynVec) -> Box<dyn Operator + 'a> {
+            return self.0.hessian(x);
+        }
+    }
+
+    #[test]
+    fn failed_line_search_keeps_the_last_point() {
+        let start = DynVec::from_element(2, 1.0);
+        let solution = optimize(&Uphill(Exponentials(vec![1.0, 2.0])), start.clone(), &Options::default()).unwrap();
+        assert_eq!(solution.reason, StopReason::LineSearch);
+        assert!(!solution.converged());
+        assert_eq!(solution.x, start);
+    }
 }
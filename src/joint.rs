@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+// A pairwise joint over two variables. Its cells are in the values of the component it was solved
+// in, where value v stands for the original values values[v], and are only split into original
+// values when asked for, since the full table has k * k entries.
+#[derive(Debug, Clone)]
+pub struct JointTable {
+    cells: HashMap<(usize, usize), f64>,
+    values: Vec<Vec<usize>>,
+    // The value each original value was lumped into.
+    index: Vec<usize>,
+    // Whether the two variables may take the same original value from a lumped value, and different ones.
+    same: bool,
+    different: bool
+}
+
+impl JointTable {
+    // A joint where every value stands for itself.
+    pub fn new(k: usize, cells: HashMap<(usize, usize), f64>) -> JointTable {
+        return JointTable{
+            cells,
+            values: (0..k).map(|value| vec![value]).collect(),
+            index: (0..k).collect(),
+            same: true,
+            different: true
+        };
+    }
+
+    // The same joint with value v standing for values[v] of a problem with k values.
+    // Transposing swaps which variable comes first.
+    pub fn expand(&self, values: &[Vec<usize>], k: usize, same: bool, different: bool, transpose: bool) -> JointTable {
+        let values: Vec<Vec<usize>> = self.values.iter()
+            .map(|lumped| lumped.iter().flat_map(|&value| values[value].iter().cloned()).collect())
+            .collect();
+        let mut index = vec![0; k];
+        for (lumped, originals) in values.iter().enumerate() {
+            for &value in originals.iter() {
+                index[value] = lumped;
+            }
+        }
+        let cells = self.cells.iter()
+            .map(|(&(first, second), &prob)| if transpose { ((second, first), prob) } else { ((first, second), prob) })
+            .collect();
+        return JointTable{
+            cells,
+            values,
+            index,
+            same: self.same && same,
+            different: self.different && different
+        };
+    }
+
+    pub fn k(&self) -> usize {
+        return self.index.len();
+    }
+
+    // Pr[first = value1, second = value2]. A lumped cell is split evenly over the pairs of original values it allows.
+    pub fn prob(&self, value1: usize, value2: usize) -> f64 {
+        let (first, second) = (self.index[value1], self.index[value2]);
+        let prob = match self.cells.get(&(first, second)) {
+            Some(&prob) => prob,
+            None => return 0.0
+        };
+        let (n1, n2) = (self.values[first].len() as f64, self.values[second].len() as f64);
+        if first != second || n1 == 1.0 {
+            return prob / (n1 * n2);
+        }
+        let allowed = if value1 == value2 { self.same } else { self.different };
+        let count = match (self.same, self.different) {
+            (true, true) => n1 * n1,
+            (true, false) => n1,
+            (false, true) => n1 * (n1 - 1.0),
+            (false, false) => return 0.0
+        };
+        return if allowed { prob / count } else { 0.0 };
+    }
+
+    // The marginals of the first and second variables as this joint sees them.
+    pub fn marginals(&self) -> (Vec<f64>, Vec<f64>) {
+        let mut rows = vec![0.0; self.values.len()];
+        let mut columns = vec![0.0; self.values.len()];
+        for (&(first, second), &prob) in self.cells.iter() {
+            rows[first] += prob;
+            columns[second] += prob;
+        }
+        let spread = |sums: &[f64]| -> Vec<f64> {
+            self.index.iter().map(|&lumped| sums[lumped] / self.values[lumped].len() as f64).collect()
+        };
+        return (spread(&rows), spread(&columns));
+    }
+
+    // In bits, how much learning one variable tells about the other.
+    pub fn mutual_information(&self) -> f64 {
+        let (first, second) = self.marginals();
+        let mut result = 0.0;
+        for (value1, &marginal1) in first.iter().enumerate() {
+            for (value2, &marginal2) in second.iter().enumerate() {
+                let prob = self.prob(value1, value2);
+                if prob > 0.0 && marginal1 > 0.0 && marginal2 > 0.0 {
+                    result += prob * (prob / (marginal1 * marginal2)).log2();
+                }
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One lumped value standing for all k original values.
+    fn lumped(k: usize, same: bool, different: bool) -> JointTable {
+        let mut cells: HashMap<(usize, usize), f64> = HashMap::new();
+        cells.insert((0, 0), 1.0);
+        return JointTable::new(1, cells).expand(&[(0..k).collect()], k, same, different, false);
+    }
+
+    fn total(joint: &JointTable) -> f64 {
+        return (0..joint.k()).flat_map(|value1| (0..joint.k()).map(move |value2| (value1, value2)))
+            .map(|(value1, value2)| joint.prob(value1, value2))
+            .sum();
+    }
+
+    #[test]
+    fn neq_splits_a_lumped_cell_over_the_different_pairs() {
+        let joint = lumped(3, false, true);
+        assert_eq!(joint.prob(1, 1), 0.0);
+        assert!((joint.prob(0, 2) - 1.0 / 6.0).abs() < 1e-15);
+        assert!((total(&joint) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn eq_keeps_only_the_diagonal() {
+        let joint = lumped(3, true, false);
+        assert!((joint.prob(2, 2) - 1.0 / 3.0).abs() < 1e-15);
+        assert_eq!(joint.prob(0, 2), 0.0);
+        assert!((total(&joint) - 1.0).abs() < 1e-12);
+        assert!((joint.mutual_information() - 3f64.log2()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn transposing_swaps_the_cells() {
+        let mut cells: HashMap<(usize, usize), f64> = HashMap::new();
+        cells.insert((0, 1), 0.7);
+        cells.insert((1, 0), 0.3);
+        let joint = JointTable::new(2, cells);
+        let transposed = joint.expand(&[vec![0], vec![1]], 2, true, true, true);
+        assert_eq!(transposed.prob(1, 0), 0.7);
+        assert_eq!(transposed.prob(0, 1), 0.3);
+        assert_eq!(joint.prob(0, 1), 0.7);
+    }
+
+    #[test]
+    fn mutual_information_is_zero_apart_and_all_of_it_when_equal() {
+        let independent: HashMap<(usize, usize), f64> = (0..4).map(|cell| ((cell / 2, cell % 2), 0.25)).collect();
+        assert!(JointTable::new(2, independent).mutual_information().abs() < 1e-15);
+        for k in 2..6 {
+            let equal: HashMap<(usize, usize), f64> = (0..k).map(|value| ((value, value), 1.0 / k as f64)).collect();
+            assert!((JointTable::new(k, equal).mutual_information() - (k as f64).log2()).abs() < 1e-12);
+        }
+    }
+}
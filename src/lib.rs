@@ -0,0 +1,25 @@
+// The code keeps the 2015 edition's try! and explicit returns.
+#![allow(deprecated, clippy::needless_return)]
+
+pub mod gradient_descent;
+pub mod dual;
+pub mod scaling;
+pub mod alchemy;
+pub mod presolve;
+pub mod exact;
+pub mod solver;
+pub mod skyrim;
+pub mod database;
+pub mod joint;
+pub mod planner;
+pub mod sampler;
+pub mod problem;
+pub mod propagation;
+pub mod cli;
+
+extern crate nalgebra;
+extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
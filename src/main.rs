@@ -1,21 +1,13 @@
-mod gradient_descent;
-mod alchemy;
-mod solver;
+extern crate skyrim_alchemy;
 
-use std::collections::HashSet;
-
-use alchemy::{EntropyConstraint, VarAndValue, EntropyOptimizer};
-
-extern crate nalgebra;
+use skyrim_alchemy::cli;
+use std::env;
+use std::process;
 
 fn main() {
-    let mut contras: HashSet<EntropyConstraint> = HashSet::new();
-    contras.insert(EntropyConstraint::SingleNeq(VarAndValue{var: 0, value: 4}));
-    contras.insert(EntropyConstraint::SingleNeq(VarAndValue{var: 1, value: 1}));
-    contras.insert(EntropyConstraint::DoubleNeq(0, 1));
-    contras.insert(EntropyConstraint::DoubleNeq(1, 2));
-    let optimizer = EntropyOptimizer{ varc:100, k: 100, contras};
-    let best = optimizer.optimize();
-    // println!("{}", best);
-    println!("{}", best.entropy());
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = cli::run(&args) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,278 @@
+use alchemy::OptimizationResult;
+use skyrim::{Beliefs, Catalog, Experiment, Ingredient, Knowledge, MagicEffect};
+use solver::EntropyConstraint;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Below this an effect is treated as impossible, above one minus this as certain.
+const CERTAIN: f64 = 1e-9;
+
+// Possible results of an experiment, each with its probability and what it would teach us.
+pub type Outcomes = Vec<(f64, Vec<EntropyConstraint>)>;
+
+// How many of each ingredient the player is carrying.
+pub type Inventory = HashMap<Ingredient, usize>;
+
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub ingredients: Vec<Ingredient>,
+    pub expected_gain: f64
+}
+
+// Later steps of a plan assume this step had its most likely outcome.
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub ingredients: Vec<Ingredient>,
+    pub expected_discoveries: f64,
+    pub assumed: Experiment
+}
+
+// Outcomes we do not enumerate are ignored, so the probabilities are renormalized.
+// Each outcome is solved starting from the current result, which is much closer than a cold start.
+pub fn expected_entropy(current: &OptimizationResult, outcomes: &Outcomes) -> Result<f64, String> {
+    let mut total = 0.0;
+    let mut weight = 0.0;
+    for &(prob, ref contras) in outcomes.iter() {
+        if prob <= 0.0 {
+            continue;
+        }
+        total += prob * try!(current.condition(contras)).entropy();
+        weight += prob;
+    }
+
+    return if weight > 0.0 {
+        Ok(total / weight)
+    } else {
+        Ok(current.entropy())
+    };
+}
+
+// The chance at least two of the ingredients have the effect.
+fn share_prob(beliefs: &Beliefs, ingredients: &[Ingredient], effect: MagicEffect) -> f64 {
+    let pair = |i: usize, j: usize| beliefs.shares_effect(ingredients[i], ingredients[j], effect);
+    return if ingredients.len() == 2 {
+        pair(0, 1)
+    } else {
+        pair(0, 1) + pair(0, 2) + pair(1, 2) - 2.0 * all_three(beliefs, ingredients, effect)
+    };
+}
+
+// The joints only cover pairs, so the second and third ingredients are taken as independent given the first.
+fn all_three(beliefs: &Beliefs, ingredients: &[Ingredient], effect: MagicEffect) -> f64 {
+    let has = beliefs.has_effect(ingredients[0], effect);
+    if has <= 0.0 {
+        return 0.0;
+    }
+    let pair = |i: usize| beliefs.shares_effect(ingredients[0], ingredients[i], effect);
+    return pair(1) * pair(2) / has;
+}
+
+pub fn experiment_outcomes(beliefs: &Beliefs, knowledge: &Knowledge, ingredients: &[Ingredient], max_outcomes: usize) -> Outcomes {
+    return likely_experiments(beliefs, knowledge, ingredients, max_outcomes).into_iter()
+        .map(|(prob, experiment)| (prob, experiment.constraints(knowledge.catalog())))
+        .collect();
+}
+
+// The effects we are sure of, alone and with each of the max_outcomes most likely extra effects.
+pub fn likely_experiments(beliefs: &Beliefs, knowledge: &Knowledge, ingredients: &[Ingredient], max_outcomes: usize) -> Vec<(f64, Experiment)> {
+    let catalog = knowledge.catalog();
+    let shared: Vec<(MagicEffect, f64)> = catalog.effects().into_iter()
+        .map(|effect| (effect, share_prob(beliefs, ingredients, effect)))
+        .collect();
+    let certain: Vec<MagicEffect> = shared.iter()
+        .filter(|&&(_, prob)| prob >= 1.0 - CERTAIN)
+        .map(|&(effect, _)| effect)
+        .collect();
+    let uncertain: Vec<(MagicEffect, f64)> = shared.iter()
+        .filter(|&&(_, prob)| prob > CERTAIN && prob < 1.0 - CERTAIN)
+        .cloned()
+        .collect();
+    let nothing_else: f64 = uncertain.iter().map(|&(_, prob)| 1.0 - prob).product();
+
+    let experiment = |extra: Option<MagicEffect>| -> Experiment {
+        let effects = certain.iter().cloned().chain(extra).collect();
+        return Experiment{ ingredients: ingredients.to_vec(), effects };
+    };
+
+    let mut singles: Vec<(f64, MagicEffect)> = uncertain.iter()
+        .map(|&(effect, prob)| (nothing_else / (1.0 - prob) * prob, effect))
+        .collect();
+    singles.sort_by(|first, second| second.0.partial_cmp(&first.0).unwrap_or(Ordering::Equal));
+
+    let mut result: Vec<(f64, Experiment)> = Vec::new();
+    result.push((nothing_else, experiment(None)));
+    for &(prob, effect) in singles.iter().take(max_outcomes) {
+        result.push((prob, experiment(Some(effect))));
+    }
+    return result;
+}
+
+pub fn candidates(inventory: &[Ingredient], triples: bool) -> Vec<Vec<Ingredient>> {
+    let mut result: Vec<Vec<Ingredient>> = Vec::new();
+    for i in 0..inventory.len() {
+        for j in (i + 1)..inventory.len() {
+            result.push(vec![inventory[i], inventory[j]]);
+            if triples {
+                for l in (j + 1)..inventory.len() {
+                    result.push(vec![inventory[i], inventory[j], inventory[l]]);
+                }
+            }
+        }
+    }
+    return result;
+}
+
+// Best experiments first.
+pub fn rank_experiments(knowledge: &Knowledge, inventory: &[Ingredient], triples: bool, max_outcomes: usize) -> Result<Vec<Suggestion>, String> {
+    let beliefs = try!(knowledge.solve());
+    let current = beliefs.entropy();
+
+    let mut result: Vec<Suggestion> = try!(candidates(inventory, triples).into_iter()
+        .map(|ingredients| {
+            let outcomes = experiment_outcomes(&beliefs, knowledge, &ingredients, max_outcomes);
+            let expected_gain = current - try!(expected_entropy(beliefs.result(), &outcomes));
+            Ok(Suggestion{ ingredients, expected_gain })
+        })
+        .collect::<Result<Vec<Suggestion>, String>>());
+    result.sort_by(|first, second| second.expected_gain.partial_cmp(&first.expected_gain).unwrap_or(Ordering::Equal));
+    return Ok(result);
+}
+
+pub fn inventory(catalog: &Catalog, counts: &HashMap<String, usize>) -> Result<Inventory, String> {
+    let mut result = Inventory::new();
+    for (name, &count) in counts.iter() {
+        match catalog.ingredient(name) {
+            Some(ingredient) => { result.insert(ingredient, count); },
+            None => return Err(format!("Unknown ingredient: {}", name))
+        }
+    }
+    return Ok(result);
+}
+
+pub fn available(inventory: &Inventory) -> Vec<Ingredient> {
+    let mut result: Vec<Ingredient> = inventory.iter()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(&ingredient, _)| ingredient)
+        .collect();
+    result.sort();
+    return result;
+}
+
+// Expected number of effects the mix reveals on ingredients that we are not already sure have them.
+pub fn expected_discoveries(beliefs: &Beliefs, knowledge: &Knowledge, ingredients: &[Ingredient]) -> f64 {
+    let mut result = 0.0;
+    for effect in knowledge.catalog().effects() {
+        for (i, &ingredient) in ingredients.iter().enumerate() {
+            if beliefs.has_effect(ingredient, effect) >= 1.0 - CERTAIN {
+                continue;
+            }
+            // The chance this ingredient and at least one of the others have it.
+            let shared: f64 = ingredients.iter().enumerate()
+                .filter(|&(j, _)| i != j)
+                .map(|(_, &other)| beliefs.shares_effect(ingredient, other, effect))
+                .sum();
+            result += if ingredients.len() == 2 { shared } else { shared - all_three(beliefs, ingredients, effect) };
+        }
+    }
+    return result;
+}
+
+// Greedily picks the mix with the most expected discoveries until nothing is left to learn or mix.
+pub fn plan(knowledge: &Knowledge, inventory: &Inventory, triples: bool, max_steps: usize) -> Result<Vec<PlanStep>, String> {
+    let mut knowledge = knowledge.clone();
+    let mut inventory = inventory.clone();
+    let mut result: Vec<PlanStep> = Vec::new();
+    let mut beliefs = try!(knowledge.solve());
+    while result.len() < max_steps {
+        if !result.is_empty() {
+            beliefs = try!(knowledge.solve_from(&beliefs));
+        }
+        let best = candidates(&available(&inventory), triples).into_iter()
+            .map(|ingredients| (expected_discoveries(&beliefs, &knowledge, &ingredients), ingredients))
+            .max_by(|first, second| first.0.partial_cmp(&second.0).unwrap_or(Ordering::Equal));
+        let (expected, ingredients) = match best {
+            Some((expected, ingredients)) => {
+                if expected <= CERTAIN {
+                    break;
+                }
+                (expected, ingredients)
+            },
+            None => break
+        };
+
+        let assumed = likely_experiments(&beliefs, &knowledge, &ingredients, 1).into_iter()
+            .max_by(|first, second| first.0.partial_cmp(&second.0).unwrap_or(Ordering::Equal))
+            .map(|(_, experiment)| experiment)
+            .expect("There is always the outcome with no uncertain effects.");
+        for ingredient in ingredients.iter() {
+            if let Some(count) = inventory.get_mut(ingredient) {
+                *count -= 1;
+            }
+        }
+        knowledge.record(assumed.clone());
+        result.push(PlanStep{ ingredients, expected_discoveries: expected, assumed });
+    }
+
+    return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database;
+
+    #[test]
+    fn candidates_are_every_pair_and_triple() {
+        let inventory: Vec<Ingredient> = (0..4).map(Ingredient).collect();
+        assert_eq!(candidates(&inventory, false).len(), 6);
+        let all = candidates(&inventory, true);
+        assert_eq!(all.len(), 10);
+        assert!(all.contains(&vec![Ingredient(1), Ingredient(2), Ingredient(3)]));
+    }
+
+    #[test]
+    fn inventory_skips_what_ran_out() {
+        let catalog = database::catalog(&["Wheat", "Salt Pile", "Blisterwort"]).unwrap();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        counts.insert("Salt Pile".to_string(), 2);
+        counts.insert("Wheat".to_string(), 0);
+        counts.insert("Blisterwort".to_string(), 1);
+        let inventory = inventory(&catalog, &counts).unwrap();
+        assert_eq!(available(&inventory), vec![Ingredient(1), Ingredient(2)]);
+
+        counts.insert("Sweetroll".to_string(), 1);
+        assert!(super::inventory(&catalog, &counts).is_err());
+    }
+
+    #[test]
+    fn a_mix_known_to_fail_ranks_below_untested_ones() {
+        // Few enough effects that a mix that works stays quick to solve, and enough that one can fail.
+        let ingredients = ["Wheat", "Salt Pile", "Blisterwort"].iter().map(|name| name.to_string()).collect();
+        let catalog = Catalog::new(ingredients, (0..10).map(|effect| format!("Effect {}", effect)).collect());
+        let mut knowledge = Knowledge::new(&catalog);
+        knowledge.record(Experiment::failed(Ingredient(0), Ingredient(1)));
+        let inventory: Vec<Ingredient> = (0..3).map(Ingredient).collect();
+        let ranked = rank_experiments(&knowledge, &inventory, false, 4).unwrap();
+        assert_eq!(ranked.len(), 3);
+        let last = ranked.last().unwrap();
+        assert_eq!(last.ingredients, vec![Ingredient(0), Ingredient(1)]);
+        assert!(last.expected_gain.abs() < 1e-6);
+        assert!(ranked[0].expected_gain > 1e-3);
+    }
+
+    #[test]
+    fn plan_does_not_repeat_a_mix_assumed_to_fail() {
+        let names = ["Wheat", "Blue Mountain Flower", "Salt Pile", "Blisterwort"];
+        let catalog = database::catalog(&names).unwrap();
+        let counts: HashMap<String, usize> = names.iter().map(|name| (name.to_string(), 2)).collect();
+        let inventory = inventory(&catalog, &counts).unwrap();
+        let steps = plan(&Knowledge::new(&catalog), &inventory, false, 3).unwrap();
+        assert_eq!(steps.len(), 3);
+        for (i, step) in steps.iter().enumerate() {
+            assert!(step.expected_discoveries > 0.0);
+            if !step.assumed.succeeded() {
+                assert!(steps[(i + 1)..].iter().all(|later| later.ingredients != step.ingredients));
+            }
+        }
+    }
+}
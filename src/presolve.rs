@@ -0,0 +1,232 @@
+use alchemy::EntropyOptimizer;
+use solver::{EntropyConstraint, VarAndValue};
+use solver::EntropyConstraint::{DoubleNeq, SingleNeq, JointNeq, SingleEq, DoubleEq, ExactlyOne, AtLeast};
+use std::cmp;
+use std::collections::HashMap;
+use std::slice;
+
+// At least min of the groups have a variable taking the value. ExactlyOne is the same with a
+// group for each variable and min one, since pairs of its variables cannot both take the value.
+struct Counting<'a> {
+    groups: Vec<&'a [usize]>,
+    value: usize,
+    min: usize
+}
+
+impl<'a> Counting<'a> {
+    // How many groups can still have a variable taking the value, with takes saying which can.
+    fn able<F: Fn(usize) -> bool>(&self, takes: F) -> usize {
+        return self.groups.iter().filter(|group| group.iter().any(|&var| takes(var))).count();
+    }
+}
+
+// The values each variable can take, narrowed before the cells are laid out. What a constraint
+// fixes or rules out is passed along the others until nothing changes, so a cell that no
+// assignment allows is left out rather than driven towards zero, which a solver only approaches.
+pub struct Domains<'a> {
+    k: usize,
+    counts: &'a [f64],
+    allowed: Vec<Vec<bool>>,
+    // The constraints naming both variables of a pair, keyed by the pair in increasing order,
+    // and the same for the counting ones by their position in counting.
+    pairs: HashMap<(usize, usize), Vec<&'a EntropyConstraint>>,
+    counted: HashMap<(usize, usize), Vec<usize>>,
+    counting: Vec<Counting<'a>>
+}
+
+impl<'a> Domains<'a> {
+    // Fails when some variable is left with no value.
+    pub fn new(optimizer: &'a EntropyOptimizer, counts: &'a [f64]) -> Result<Domains<'a>, String> {
+        let k = optimizer.k;
+        let mut allowed = vec![vec![true; k]; optimizer.varc];
+        let mut pairs: HashMap<(usize, usize), Vec<&EntropyConstraint>> = HashMap::new();
+        let mut counted: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut counting: Vec<Counting> = Vec::new();
+        for contra in optimizer.contras.iter() {
+            match *contra {
+                SingleNeq(varval) => {
+                    allowed[varval.var][varval.value] = false;
+                },
+                SingleEq(varval) => {
+                    for value in (0..k).filter(|&value| value != varval.value) {
+                        allowed[varval.var][value] = false;
+                    }
+                },
+                ExactlyOne(ref vars, value) => {
+                    counting.push(Counting{ groups: vars.iter().map(slice::from_ref).collect(), value, min: 1 });
+                },
+                AtLeast(ref groups, count, value) => {
+                    counting.push(Counting{ groups: groups.iter().map(|group| &group[..]).collect(), value, min: count });
+                },
+                DoubleNeq(_, _) | DoubleEq(_, _) | JointNeq(_, _) => {}
+            }
+
+            let mut vars = contra.variables();
+            vars.sort();
+            vars.dedup();
+            for (i, &first) in vars.iter().enumerate() {
+                for &second in vars[(i + 1)..].iter() {
+                    pairs.entry((first, second)).or_default().push(contra);
+                    if let ExactlyOne(_, _) | AtLeast(_, _, _) = *contra {
+                        counted.entry((first, second)).or_default().push(counting.len() - 1);
+                    }
+                }
+            }
+        }
+
+        let mut domains = Domains{ k, counts, allowed, pairs, counted, counting };
+        try!(domains.propagate());
+        return Ok(domains);
+    }
+
+    pub fn allows(&self, var: usize, value: usize) -> bool {
+        return self.allowed[var][value];
+    }
+
+    // How many cells of the full joint table the cell stands for, or zero if it is ruled out.
+    pub fn cell_count(&self, var1: usize, val1: usize, var2: usize, val2: usize) -> f64 {
+        if !self.allowed[var1][val1] || !self.allowed[var2][val2] {
+            return 0.0;
+        }
+
+        // A lumped value pairs with itself both as equal and as different values.
+        let (mut same, mut different) = if val1 == val2 {
+            let count = self.counts[val1];
+            (count, count * (count - 1.0))
+        } else {
+            (0.0, self.counts[val1] * self.counts[val2])
+        };
+        let both = |value: usize| val1 == value && val2 == value;
+        let pair = (cmp::min(var1, var2), cmp::max(var1, var2));
+        for contra in self.pairs.get(&pair).map(|contras| &contras[..]).unwrap_or(&[]) {
+            match **contra {
+                DoubleNeq(_, _) => same = 0.0,
+                DoubleEq(_, _) => different = 0.0,
+                JointNeq(first, second) => {
+                    let matches = |one: VarAndValue, two: VarAndValue| {
+                        var1 == one.var && val1 == one.value && var2 == two.var && val2 == two.value
+                    };
+                    if matches(first, second) || matches(second, first) {
+                        return 0.0;
+                    }
+                },
+                ExactlyOne(_, value) if both(value) => return 0.0,
+                AtLeast(ref groups, _, value) if both(value) &&
+                    groups.iter().any(|group| group.contains(&var1) && group.contains(&var2)) => return 0.0,
+                _ => {}
+            }
+        }
+
+        // Too few groups left that can take the value once the pair has taken theirs.
+        for &c in self.counted.get(&pair).map(|counted| &counted[..]).unwrap_or(&[]) {
+            let counting = &self.counting[c];
+            let able = counting.able(|var| {
+                if var == var1 {
+                    val1 == counting.value
+                } else if var == var2 {
+                    val2 == counting.value
+                } else {
+                    self.allowed[var][counting.value]
+                }
+            });
+            if able < counting.min {
+                return 0.0;
+            }
+        }
+        return same + different;
+    }
+
+    // Arc consistency over the pairs, along with counting. When only as many groups can take the
+    // value as must, each of them does, so a group left with one variable that can has it take the value.
+    fn propagate(&mut self) -> Result<(), String> {
+        let k = self.k;
+        let pairs: Vec<(usize, usize)> = self.pairs.keys().cloned().collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(first, second) in pairs.iter() {
+                for &(var, other) in [(first, second), (second, first)].iter() {
+                    for value in 0..k {
+                        if self.allowed[var][value] && !(0..k).any(|theirs| self.cell_count(var, value, other, theirs) > 0.0) {
+                            self.allowed[var][value] = false;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+
+            let mut fixed: Vec<(usize, usize)> = Vec::new();
+            for counting in self.counting.iter() {
+                let value = counting.value;
+                let able = counting.able(|var| self.allowed[var][value]);
+                if able < counting.min {
+                    return Err("No assignment satisfies the constraints.".to_string());
+                }
+                if able == counting.min {
+                    for group in counting.groups.iter() {
+                        let takers: Vec<usize> = group.iter().cloned().filter(|&var| self.allowed[var][value]).collect();
+                        if takers.len() == 1 {
+                            fixed.push((takers[0], value));
+                        }
+                    }
+                }
+            }
+            for (var, value) in fixed {
+                for other in (0..k).filter(|&other| other != value) {
+                    if self.allowed[var][other] {
+                        self.allowed[var][other] = false;
+                        changed = true;
+                    }
+                }
+            }
+
+            if self.allowed.iter().any(|values| !values.contains(&true)) {
+                return Err("No assignment satisfies the constraints.".to_string());
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(varc: usize, k: usize, contras: Vec<EntropyConstraint>) -> EntropyOptimizer {
+        return EntropyOptimizer{ varc, k, contras: contras.into_iter().collect() };
+    }
+
+    #[test]
+    fn exactly_one_fixes_the_last_variable_that_can_take_the_value() {
+        let optimizer = problem(3, 3, vec![
+            ExactlyOne(vec![0, 1, 2], 2),
+            SingleNeq(VarAndValue{ var: 0, value: 2 }),
+            DoubleEq(0, 1)
+        ]);
+        let domains = Domains::new(&optimizer, &[1.0, 1.0, 1.0]).unwrap();
+        assert_eq!(domains.allowed[2], vec![false, false, true]);
+        assert_eq!(domains.allowed[1], vec![true, true, false]);
+        assert_eq!(domains.cell_count(0, 0, 1, 1), 0.0);
+        assert_eq!(domains.cell_count(0, 1, 1, 1), 1.0);
+    }
+
+    #[test]
+    fn lumped_values_can_differ_from_themselves() {
+        let optimizer = problem(2, 2, vec![DoubleNeq(0, 1), SingleNeq(VarAndValue{ var: 1, value: 0 })]);
+        let domains = Domains::new(&optimizer, &[1.0, 3.0]).unwrap();
+        assert_eq!(domains.cell_count(0, 1, 1, 1), 6.0);
+        assert_eq!(domains.cell_count(0, 0, 1, 1), 3.0);
+        assert!(!domains.allows(1, 0));
+    }
+
+    #[test]
+    fn no_value_left_is_an_error() {
+        let optimizer = problem(3, 2, vec![
+            SingleEq(VarAndValue{ var: 0, value: 0 }),
+            DoubleNeq(0, 1),
+            DoubleNeq(1, 2),
+            DoubleNeq(0, 2)
+        ]);
+        assert!(Domains::new(&optimizer, &[1.0, 1.0]).is_err());
+    }
+}
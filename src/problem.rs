@@ -0,0 +1,414 @@
+use alchemy::EntropyOptimizer;
+use solver::{EntropyConstraint, VarAndValue};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter, Write};
+use std::fmt;
+
+// One statement per line, and # starts a comment:
+//   vars 100
+//   values 100
+//   var fire = 0          names are optional and can be used in place of numbers
+//   value red = 4
+//   neq A B               A and B differ
+//   eq A B                A and B are the same
+//   not A = V             A is not V
+//   is A = V              A is V
+//   never A = V B = W     A is not V or B is not W
+//   one A B ... = V       exactly one of the variables is V
+//   atleast N A B | C D ... = V
+//                         at least N of the groups have a variable that is V
+// vars and values come before anything that uses them, and neq, eq, never, one and each group
+// of atleast name a variable at most once.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize
+}
+
+impl<'a> Token<'a> {
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        return Err(ParseError{ line: self.line, column: self.column, message });
+    }
+}
+
+struct Tokens<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    line: usize,
+    end: usize
+}
+
+impl<'a> Tokens<'a> {
+    // Columns count characters from 1. = and | are tokens even without spaces around them.
+    fn new(text: &'a str, line: usize) -> Tokens<'a> {
+        let mut tokens: Vec<Token<'a>> = Vec::new();
+        let mut start: Option<(usize, usize)> = None;
+        let mut column = 0;
+        let mut end = text.len();
+        for (i, c) in text.char_indices() {
+            column += 1;
+            if c == '#' {
+                end = i;
+                break;
+            }
+            let single = c == '=' || c == '|';
+            if c.is_whitespace() || single {
+                if let Some((begin, begin_column)) = start.take() {
+                    tokens.push(Token{ text: &text[begin..i], line, column: begin_column });
+                }
+                if single {
+                    tokens.push(Token{ text: &text[i..(i + 1)], line, column });
+                }
+            } else if start.is_none() {
+                start = Some((i, column));
+            }
+        }
+        if let Some((begin, begin_column)) = start {
+            tokens.push(Token{ text: &text[begin..end], line, column: begin_column });
+        }
+        let end = text[..end].chars().count() + 1;
+
+        return Tokens{ tokens, pos: 0, line, end };
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        return self.tokens.get(self.pos).cloned();
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token<'a>, ParseError> {
+        return match self.peek() {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token)
+            },
+            None => Err(ParseError{ line: self.line, column: self.end, message: format!("expected {}", expected) })
+        };
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), ParseError> {
+        let token = try!(self.next(&format!("'{}'", text)));
+        return if token.text == text {
+            Ok(())
+        } else {
+            token.error(format!("expected '{}', found '{}'", text, token.text))
+        };
+    }
+
+    fn finish(&self) -> Result<(), ParseError> {
+        return match self.peek() {
+            Some(token) => token.error(format!("unexpected '{}'", token.text)),
+            None => Ok(())
+        };
+    }
+}
+
+fn parse_number(token: Token) -> Result<usize, ParseError> {
+    return match token.text.parse::<usize>() {
+        Ok(number) => Ok(number),
+        Err(_) => token.error(format!("expected a number, found '{}'", token.text))
+    };
+}
+
+pub struct Problem {
+    pub optimizer: EntropyOptimizer,
+    pub var_names: BTreeMap<String, usize>,
+    pub value_names: BTreeMap<String, usize>
+}
+
+pub fn parse(text: &str) -> Result<Problem, ParseError> {
+    let mut problem = Problem{
+        optimizer: EntropyOptimizer{ varc: 0, k: 0, contras: HashSet::new() },
+        var_names: BTreeMap::new(),
+        value_names: BTreeMap::new()
+    };
+    let mut last_line = 0;
+    for (i, line) in text.lines().enumerate() {
+        let mut tokens = Tokens::new(line, i + 1);
+        last_line = i + 1;
+        if tokens.peek().is_none() {
+            continue;
+        }
+        try!(problem.statement(&mut tokens));
+        try!(tokens.finish());
+    }
+
+    if problem.optimizer.varc == 0 || problem.optimizer.k == 0 {
+        return Err(ParseError{ line: last_line + 1, column: 1, message: "missing vars or values".to_string() });
+    }
+    return Ok(problem);
+}
+
+impl Problem {
+    // Reads a single constraint using this problem's names and sizes.
+    pub fn parse_constraint(&self, text: &str) -> Result<EntropyConstraint, ParseError> {
+        let mut tokens = Tokens::new(text, 1);
+        let first = try!(tokens.next("a constraint"));
+        let contra = try!(self.constraint(first, &mut tokens));
+        try!(tokens.finish());
+        return Ok(contra);
+    }
+
+    fn statement(&mut self, tokens: &mut Tokens) -> Result<(), ParseError> {
+        let first = try!(tokens.next("a statement"));
+        match first.text {
+            "vars" | "values" => {
+                let token = try!(tokens.next("a count"));
+                let count = try!(parse_number(token));
+                if count == 0 {
+                    return token.error("count must be positive".to_string());
+                }
+                let size = if first.text == "vars" {
+                    &mut self.optimizer.varc
+                } else {
+                    &mut self.optimizer.k
+                };
+                if *size != 0 {
+                    return first.error(format!("{} is already set", first.text));
+                }
+                *size = count;
+            },
+            "var" | "value" => {
+                let name = try!(tokens.next("a name"));
+                if name.text.parse::<usize>().is_ok() || name.text == "=" || name.text == "|" {
+                    return name.error(format!("'{}' cannot be a name", name.text));
+                }
+                try!(tokens.expect("="));
+                let number = if first.text == "var" {
+                    try!(self.var(try!(tokens.next("a variable"))))
+                } else {
+                    try!(self.value(try!(tokens.next("a value"))))
+                };
+                let names = if first.text == "var" {
+                    &mut self.var_names
+                } else {
+                    &mut self.value_names
+                };
+                if names.contains_key(name.text) {
+                    return name.error(format!("{} '{}' is already named", first.text, name.text));
+                }
+                names.insert(name.text.to_string(), number);
+            },
+            _ => {
+                let contra = try!(self.constraint(first, tokens));
+                self.optimizer.contras.insert(contra);
+            }
+        }
+        return Ok(());
+    }
+
+    fn constraint(&self, first: Token, tokens: &mut Tokens) -> Result<EntropyConstraint, ParseError> {
+        return match first.text {
+            "neq" | "eq" => {
+                let var1 = try!(self.var(try!(tokens.next("a variable"))));
+                let token = try!(tokens.next("a variable"));
+                let var2 = try!(self.var(token));
+                if var1 == var2 {
+                    return token.error(format!("variable '{}' is repeated", token.text));
+                }
+                Ok(if first.text == "neq" {
+                    EntropyConstraint::DoubleNeq(var1, var2)
+                } else {
+                    EntropyConstraint::DoubleEq(var1, var2)
+                })
+            },
+            "not" => self.varval(tokens).map(EntropyConstraint::SingleNeq),
+            "is" => self.varval(tokens).map(EntropyConstraint::SingleEq),
+            "never" => {
+                let varval1 = try!(self.varval(tokens));
+                let second = tokens.peek();
+                let varval2 = try!(self.varval(tokens));
+                if varval1.var == varval2.var {
+                    let token = second.expect("The second variable was read.");
+                    return token.error(format!("variable '{}' is repeated", token.text));
+                }
+                Ok(EntropyConstraint::JointNeq(varval1, varval2))
+            },
+            "one" => {
+                let groups = try!(self.groups(tokens, false));
+                let value = try!(self.value(try!(tokens.next("a value"))));
+                Ok(EntropyConstraint::ExactlyOne(groups.into_iter().next().unwrap(), value))
+            },
+            "atleast" => {
+                let count = try!(parse_number(try!(tokens.next("a count"))));
+                let groups = try!(self.groups(tokens, true));
+                if count > groups.len() {
+                    return first.error(format!("at least {} of only {} groups", count, groups.len()));
+                }
+                let value = try!(self.value(try!(tokens.next("a value"))));
+                Ok(EntropyConstraint::AtLeast(groups, count, value))
+            },
+            other => first.error(format!("unknown statement '{}'", other))
+        };
+    }
+
+    // Variables up to and including the =, split on | when there can be several groups.
+    // A group names each variable once.
+    fn groups(&self, tokens: &mut Tokens, several: bool) -> Result<Vec<Vec<usize>>, ParseError> {
+        let mut groups: Vec<Vec<usize>> = vec![Vec::new()];
+        loop {
+            let token = try!(tokens.next("a variable or '='"));
+            if token.text == "=" || (several && token.text == "|") {
+                if groups.last().unwrap().is_empty() {
+                    return token.error("expected a variable".to_string());
+                }
+                if token.text == "=" {
+                    return Ok(groups);
+                }
+                groups.push(Vec::new());
+            } else {
+                let var = try!(self.var(token));
+                if groups.last().unwrap().contains(&var) {
+                    return token.error(format!("variable '{}' is repeated", token.text));
+                }
+                groups.last_mut().unwrap().push(var);
+            }
+        }
+    }
+
+    fn varval(&self, tokens: &mut Tokens) -> Result<VarAndValue, ParseError> {
+        let var = try!(self.var(try!(tokens.next("a variable"))));
+        try!(tokens.expect("="));
+        let value = try!(self.value(try!(tokens.next("a value"))));
+        return Ok(VarAndValue{ var, value });
+    }
+
+    fn var(&self, token: Token) -> Result<usize, ParseError> {
+        return lookup(token, &self.var_names, self.optimizer.varc, "variable", "vars");
+    }
+
+    fn value(&self, token: Token) -> Result<usize, ParseError> {
+        return lookup(token, &self.value_names, self.optimizer.k, "value", "values");
+    }
+
+    pub fn var_name(&self, var: usize) -> String {
+        return name_of(&self.var_names, var);
+    }
+
+    pub fn value_name(&self, value: usize) -> String {
+        return name_of(&self.value_names, value);
+    }
+
+    pub fn format_constraint(&self, contra: &EntropyConstraint) -> String {
+        let vars = |vars: &[usize]| -> String {
+            vars.iter().map(|&var| self.var_name(var)).collect::<Vec<String>>().join(" ")
+        };
+        let varval = |varval: VarAndValue| -> String {
+            format!("{} = {}", self.var_name(varval.var), self.value_name(varval.value))
+        };
+        return match *contra {
+            EntropyConstraint::DoubleNeq(var1, var2) => format!("neq {}", vars(&[var1, var2])),
+            EntropyConstraint::DoubleEq(var1, var2) => format!("eq {}", vars(&[var1, var2])),
+            EntropyConstraint::SingleNeq(varval1) => format!("not {}", varval(varval1)),
+            EntropyConstraint::SingleEq(varval1) => format!("is {}", varval(varval1)),
+            EntropyConstraint::JointNeq(varval1, varval2) => format!("never {} {}", varval(varval1), varval(varval2)),
+            EntropyConstraint::ExactlyOne(ref group, value) => format!("one {} = {}", vars(group), self.value_name(value)),
+            EntropyConstraint::AtLeast(ref groups, count, value) => {
+                let groups: Vec<String> = groups.iter().map(|group| vars(group)).collect();
+                format!("atleast {} {} = {}", count, groups.join(" | "), self.value_name(value))
+            }
+        };
+    }
+}
+
+fn lookup(token: Token, names: &BTreeMap<String, usize>, size: usize, kind: &str, size_name: &str) -> Result<usize, ParseError> {
+    if size == 0 {
+        return token.error(format!("{} must come before any {}", size_name, kind));
+    }
+    let number = match names.get(token.text) {
+        Some(&number) => number,
+        None => match token.text.parse::<usize>() {
+            Ok(number) => number,
+            Err(_) => return token.error(format!("unknown {} '{}'", kind, token.text))
+        }
+    };
+    return if number < size {
+        Ok(number)
+    } else {
+        token.error(format!("{} {} is not below {} {}", kind, number, size_name, size))
+    };
+}
+
+fn name_of(names: &BTreeMap<String, usize>, number: usize) -> String {
+    return names.iter()
+        .find(|&(_, &named)| named == number)
+        .map(|(name, _)| name.clone())
+        .unwrap_or(number.to_string());
+}
+
+// Constraints come out sorted so the same problem always prints the same way.
+pub fn format(problem: &Problem) -> String {
+    let mut result = String::new();
+    writeln!(result, "vars {}", problem.optimizer.varc).unwrap();
+    writeln!(result, "values {}", problem.optimizer.k).unwrap();
+
+    let mut vars: Vec<(&usize, &String)> = problem.var_names.iter().map(|(name, var)| (var, name)).collect();
+    vars.sort();
+    for (var, name) in vars {
+        writeln!(result, "var {} = {}", name, var).unwrap();
+    }
+    let mut values: Vec<(&usize, &String)> = problem.value_names.iter().map(|(name, value)| (value, name)).collect();
+    values.sort();
+    for (value, name) in values {
+        writeln!(result, "value {} = {}", name, value).unwrap();
+    }
+
+    let mut lines: Vec<String> = problem.optimizer.contras.iter().map(|contra| problem.format_constraint(contra)).collect();
+    lines.sort();
+    for line in lines {
+        writeln!(result, "{}", line).unwrap();
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(text: &str) -> (usize, usize) {
+        let err = parse(text).err().expect("The problem should not parse.");
+        return (err.line, err.column);
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        let head = "vars 3\nvalues 2\nvar a = 0\n";
+        assert_eq!(error_at(&format!("{}neq a a\n", head)), (4, 7));
+        assert_eq!(error_at(&format!("{}eq 0 a\n", head)), (4, 6));
+        assert_eq!(error_at(&format!("{}one a 1 a = 1\n", head)), (4, 9));
+        assert_eq!(error_at(&format!("{}atleast 1 a | 1 1 = 0\n", head)), (4, 17));
+        assert_eq!(error_at(&format!("{}neq a 3\n", head)), (4, 7));
+        assert_eq!(error_at(&format!("{}never a = 0 0 = 1\n", head)), (4, 13));
+        assert_eq!(error_at(&format!("{}\n  never a=2 1 = 0\n", head)), (5, 11));
+        assert!(parse(&format!("{}atleast 1 a | a = 0\n", head)).is_ok());
+    }
+
+    #[test]
+    fn format_reads_back_as_the_same_problem() {
+        let text = "vars 4\nvalues 3\nvar fire = 0\nvalue red = 2\n\
+                    neq fire 1\neq 2 3\nnot fire = red\nis 1 = 0\nnever 2 = 1 3 = red\n\
+                    one fire 1 2 = red\natleast 2 fire | 1 2 | 3 = 1\n";
+        let problem = parse(text).unwrap();
+        assert_eq!(problem.optimizer.contras.len(), 7);
+        let again = parse(&format(&problem)).unwrap();
+        assert_eq!(again.optimizer.contras, problem.optimizer.contras);
+        assert_eq!((again.optimizer.varc, again.optimizer.k), (4, 3));
+        assert_eq!(again.var_names, problem.var_names);
+        assert_eq!(again.value_names, problem.value_names);
+        assert_eq!(format(&again), format(&problem));
+    }
+}
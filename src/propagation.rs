@@ -0,0 +1,276 @@
+use alchemy::EntropyOptimizer;
+use gradient_descent::{Options, StopReason};
+use solver::{EntropyConstraint, VarAndValue};
+use std::collections::HashMap;
+
+pub const MAX_ITERATIONS: usize = 1000;
+// Each message moves halfway to its new value, which stops loops from oscillating.
+const DAMPING: f64 = 0.5;
+
+enum Factor {
+    // table[a * k + b] is how much the pair allows first = a and second = b.
+    Pair{ first: usize, second: usize, table: Vec<f64> },
+    // No group has more than one variable taking the value, and between min and max groups have one.
+    Counting{ groups: Vec<Vec<usize>>, value: usize, min: usize, max: usize }
+}
+
+impl Factor {
+    fn variables(&self) -> Vec<usize> {
+        return match *self {
+            Factor::Pair{ first, second, .. } => vec![first, second],
+            Factor::Counting{ ref groups, .. } => groups.iter().flat_map(|group| group.iter().cloned()).collect()
+        };
+    }
+
+    // The message to the variable at position, given the messages from every variable in the factor.
+    fn message(&self, k: usize, position: usize, incoming: &[Vec<f64>]) -> Vec<f64> {
+        return match *self {
+            Factor::Pair{ ref table, .. } => {
+                let other = &incoming[1 - position];
+                (0..k).map(|mine| {
+                    (0..k).map(|theirs| {
+                        let cell = if position == 0 { mine * k + theirs } else { theirs * k + mine };
+                        table[cell] * other[theirs]
+                    }).sum()
+                }).collect()
+            },
+            Factor::Counting{ ref groups, value, min, max } => {
+                // Each variable only matters through whether it takes the value.
+                let takes: Vec<f64> = incoming.iter().map(|message| message[value]).collect();
+                let not: Vec<f64> = incoming.iter()
+                    .map(|message| message.iter().sum::<f64>() - message[value])
+                    .collect();
+                let in_range = |count: usize| count >= min && count <= max;
+
+                // Weight of a group having no variable, or exactly one, taking the value.
+                let group_weights = |members: &[usize]| -> (f64, f64) {
+                    let none: f64 = members.iter().map(|&i| not[i]).product();
+                    let one: f64 = members.iter()
+                        .map(|&i| takes[i] * members.iter().filter(|&&j| j != i).map(|&j| not[j]).product::<f64>())
+                        .sum();
+                    (none, one)
+                };
+
+                // Positions are numbered through the groups in order, as in variables.
+                let mut start = 0;
+                let mut mine = 0;
+                for (g, group) in groups.iter().enumerate() {
+                    if position < start + group.len() {
+                        mine = g;
+                        break;
+                    }
+                    start += group.len();
+                }
+
+                // poly[c] is the weight of exactly c of the other groups having a variable taking the value.
+                let mut poly: Vec<f64> = vec![1.0];
+                let mut offset = 0;
+                for (g, group) in groups.iter().enumerate() {
+                    let members: Vec<usize> = (offset..(offset + group.len())).collect();
+                    offset += group.len();
+                    if g == mine {
+                        continue;
+                    }
+                    let (none, one) = group_weights(&members);
+                    let mut next = vec![0.0; poly.len() + 1];
+                    for (c, &weight) in poly.iter().enumerate() {
+                        next[c] += weight * none;
+                        next[c + 1] += weight * one;
+                    }
+                    poly = next;
+                }
+
+                let rest: Vec<usize> = (start..(start + groups[mine].len())).filter(|&i| i != position).collect();
+                let (none, one) = group_weights(&rest);
+                let mut taking = 0.0;
+                let mut other = 0.0;
+                for (c, &weight) in poly.iter().enumerate() {
+                    if in_range(c + 1) {
+                        taking += weight * none;
+                        other += weight * one;
+                    }
+                    if in_range(c) {
+                        other += weight * none;
+                    }
+                }
+                (0..k).map(|v| if v == value { taking } else { other }).collect()
+            }
+        };
+    }
+}
+
+fn normalize(message: &mut [f64]) -> Result<(), String> {
+    let total: f64 = message.iter().sum();
+    if total.is_nan() || total <= 0.0 {
+        return Err("Belief propagation found no consistent values.".to_string());
+    }
+    for entry in message.iter_mut() {
+        *entry /= total;
+    }
+    return Ok(());
+}
+
+pub struct Marginals {
+    pub distribution: HashMap<VarAndValue, f64>,
+    // AbsoluteResidual once no message changes by more than the tolerance, else MaxIterations.
+    pub reason: StopReason
+}
+
+// Loopy belief propagation on the factor graph with a node for each variable and a factor for
+// each constraint. It is exact when the graph has no loops, and otherwise approximates the
+// marginals when every consistent assignment is equally likely, in time linear in the constraints.
+pub fn marginals(optimizer: &EntropyOptimizer, counts: &[f64], options: &Options) -> Result<Marginals, String> {
+    let k = optimizer.k;
+    let varc = optimizer.varc;
+
+    // A value standing for several is that many times as likely before any constraint.
+    let mut prior: Vec<Vec<f64>> = vec![counts.to_vec(); varc];
+    let mut factors: Vec<Factor> = Vec::new();
+    for contra in optimizer.contras.iter() {
+        match *contra {
+            EntropyConstraint::SingleNeq(varval) => {
+                prior[varval.var][varval.value] = 0.0;
+            },
+            EntropyConstraint::SingleEq(varval) => {
+                for value in (0..k).filter(|&value| value != varval.value) {
+                    prior[varval.var][value] = 0.0;
+                }
+            },
+            // The share of the pairs of original values two lumped values stand for that are allowed.
+            EntropyConstraint::DoubleNeq(first, second) => {
+                let table = (0..(k * k))
+                    .map(|cell| if cell / k == cell % k { (counts[cell % k] - 1.0) / counts[cell % k] } else { 1.0 })
+                    .collect();
+                factors.push(Factor::Pair{ first, second, table });
+            },
+            EntropyConstraint::DoubleEq(first, second) => {
+                let table = (0..(k * k))
+                    .map(|cell| if cell / k == cell % k { 1.0 / counts[cell % k] } else { 0.0 })
+                    .collect();
+                factors.push(Factor::Pair{ first, second, table });
+            },
+            EntropyConstraint::JointNeq(first, second) => {
+                let mut table = vec![1.0; k * k];
+                table[first.value * k + second.value] = 0.0;
+                factors.push(Factor::Pair{ first: first.var, second: second.var, table });
+            },
+            EntropyConstraint::ExactlyOne(ref vars, value) => {
+                let groups = vars.iter().map(|&var| vec![var]).collect();
+                factors.push(Factor::Counting{ groups, value, min: 1, max: 1 });
+            },
+            EntropyConstraint::AtLeast(ref groups, count, value) => {
+                factors.push(Factor::Counting{ groups: groups.clone(), value, min: count, max: groups.len() });
+            }
+        }
+    }
+
+    let variables: Vec<Vec<usize>> = factors.iter().map(|factor| factor.variables()).collect();
+    let mut incident: Vec<Vec<(usize, usize)>> = vec![Vec::new(); varc];
+    for (f, vars) in variables.iter().enumerate() {
+        for (position, &var) in vars.iter().enumerate() {
+            incident[var].push((f, position));
+        }
+    }
+
+    let uniform = vec![1.0 / k as f64; k];
+    let mut to_var: Vec<Vec<Vec<f64>>> = variables.iter().map(|vars| vec![uniform.clone(); vars.len()]).collect();
+    let mut to_factor = to_var.clone();
+
+    let mut iterations = 0;
+    let mut reason = StopReason::MaxIterations;
+    while iterations < options.max_iter {
+        for var in 0..varc {
+            for &(f, position) in incident[var].iter() {
+                let mut message = prior[var].clone();
+                for &(g, other) in incident[var].iter().filter(|&&pair| pair != (f, position)) {
+                    for value in 0..k {
+                        message[value] *= to_var[g][other][value];
+                    }
+                }
+                try!(normalize(&mut message));
+                to_factor[f][position] = message;
+            }
+        }
+
+        let mut change: f64 = 0.0;
+        for (f, factor) in factors.iter().enumerate() {
+            for (position, old) in to_var[f].iter_mut().enumerate() {
+                let mut message = factor.message(k, position, &to_factor[f]);
+                try!(normalize(&mut message));
+                for (entry, &new) in old.iter_mut().zip(message.iter()) {
+                    let damped = DAMPING * *entry + (1.0 - DAMPING) * new;
+                    change = change.max((damped - *entry).abs());
+                    *entry = damped;
+                }
+            }
+        }
+        iterations += 1;
+        if change <= options.absolute_tolerance {
+            reason = StopReason::AbsoluteResidual;
+            break;
+        }
+    }
+
+    let mut distribution: HashMap<VarAndValue, f64> = HashMap::new();
+    for var in 0..varc {
+        let mut belief = prior[var].clone();
+        for &(f, position) in incident[var].iter() {
+            for (entry, &message) in belief.iter_mut().zip(to_var[f][position].iter()) {
+                *entry *= message;
+            }
+        }
+        try!(normalize(&mut belief));
+        for (value, &prob) in belief.iter().enumerate() {
+            distribution.insert(VarAndValue{ var, value }, prob);
+        }
+    }
+    return Ok(Marginals{ distribution, reason });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exact;
+    use solver::EntropyConstraint::*;
+
+    fn problem(varc: usize, k: usize, contras: Vec<EntropyConstraint>) -> EntropyOptimizer {
+        return EntropyOptimizer{ varc, k, contras: contras.into_iter().collect() };
+    }
+
+    #[test]
+    fn trees_get_the_exact_marginals() {
+        // A path 0 - 1 - 2 - 3 of factors, with value 2 standing for two values.
+        let optimizer = problem(4, 3, vec![
+            DoubleNeq(0, 1),
+            JointNeq(VarAndValue{ var: 1, value: 0 }, VarAndValue{ var: 2, value: 1 }),
+            ExactlyOne(vec![2, 3], 0),
+            SingleNeq(VarAndValue{ var: 0, value: 1 })
+        ]);
+        let counts = [1.0, 1.0, 2.0];
+        let options = Options{ max_iter: MAX_ITERATIONS, absolute_tolerance: 1e-14, ..Options::default() };
+        let found = marginals(&optimizer, &counts, &options).unwrap();
+        assert_eq!(found.reason, StopReason::AbsoluteResidual);
+        let expected = exact::marginals(&optimizer, &counts, &Options::default()).unwrap();
+        for (varval, &prob) in expected.distribution.iter() {
+            assert!((found.distribution[varval] - prob).abs() < 1e-10, "{:?}: {} against {}", varval, found.distribution[varval], prob);
+        }
+    }
+
+    #[test]
+    fn loops_stop_at_the_tolerance_or_the_iteration_limit() {
+        let optimizer = problem(3, 3, vec![
+            DoubleNeq(0, 1),
+            DoubleNeq(1, 2),
+            DoubleNeq(0, 2),
+            SingleNeq(VarAndValue{ var: 0, value: 0 })
+        ]);
+        let counts = [1.0, 1.0, 1.0];
+        let settled = marginals(&optimizer, &counts, &Options{ max_iter: MAX_ITERATIONS, ..Options::default() }).unwrap();
+        assert_eq!(settled.reason, StopReason::AbsoluteResidual);
+        let total: f64 = (0..3).map(|value| settled.distribution[&VarAndValue{ var: 1, value }]).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        let cut_short = marginals(&optimizer, &counts, &Options{ max_iter: 2, ..Options::default() }).unwrap();
+        assert_eq!(cut_short.reason, StopReason::MaxIterations);
+    }
+}
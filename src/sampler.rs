@@ -0,0 +1,227 @@
+use alchemy::EntropyOptimizer;
+use exact;
+use presolve::Domains;
+use rayon::prelude::*;
+use solver::{EntropyConstraint, VarAndValue};
+use std::collections::{BTreeSet, HashMap};
+use std::f64;
+
+// Sweeps kept per chain, each proposing one move per variable. A tenth as many again are burned in first.
+pub const SWEEPS: usize = 10000;
+// Chains are run independently, and the spread of their estimates gives the error bars.
+const CHAINS: usize = 4;
+const SEED: u64 = 0x9E3779B97F4A7C15;
+// A marginal has converged once its R-hat is below this.
+const MAX_R_HAT: f64 = 1.01;
+
+// xorshift64*, so that runs are reproducible without another dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        return self.0.wrapping_mul(0x2545F4914F6CDD1D);
+    }
+
+    // The modulo bias is negligible for the small ranges used here.
+    fn below(&mut self, n: usize) -> usize {
+        return (self.next() % n as u64) as usize;
+    }
+
+    // 0..n in a random order.
+    fn shuffled(&mut self, n: usize) -> Vec<usize> {
+        let mut result: Vec<usize> = (0..n).collect();
+        for i in (1..n).rev() {
+            let j = self.below(i + 1);
+            result.swap(i, j);
+        }
+        return result;
+    }
+}
+
+pub struct Estimate {
+    pub marginals: HashMap<VarAndValue, f64>,
+    // Standard errors of the marginals.
+    pub errors: HashMap<VarAndValue, f64>,
+    // The variables with a marginal whose R-hat is at least MAX_R_HAT.
+    pub unconverged: BTreeSet<usize>
+}
+
+struct Chain<'a> {
+    k: usize,
+    counts: &'a [usize],
+    watching: &'a [Vec<&'a EntropyConstraint>],
+    // The variables DoubleEq ties to each one, itself included, which only move together.
+    tied: &'a [Vec<usize>],
+    assignment: Vec<Option<usize>>,
+    rng: Rng
+}
+
+impl<'a> Chain<'a> {
+    fn consistent(&self, var: usize) -> bool {
+        return self.watching[var].iter().all(|contra| contra.satisfied(&self.assignment));
+    }
+
+    // Gives every variable of each block that block's label, and undoes it unless the constraints still hold.
+    fn try_set(&mut self, blocks: &[&[usize]], labels: &[Option<usize>]) {
+        let old: Vec<Vec<Option<usize>>> = blocks.iter()
+            .map(|block| block.iter().map(|&var| self.assignment[var]).collect())
+            .collect();
+        for (block, &label) in blocks.iter().zip(labels.iter()) {
+            for &var in block.iter() {
+                self.assignment[var] = label;
+            }
+        }
+        if blocks.iter().all(|block| block.iter().all(|&var| self.consistent(var))) {
+            return;
+        }
+        for (block, labels) in blocks.iter().zip(old.iter()) {
+            for (&var, &label) in block.iter().zip(labels.iter()) {
+                self.assignment[var] = label;
+            }
+        }
+    }
+
+    // Every assignment of the original values is equally likely, so a proposal is accepted
+    // exactly when it keeps the constraints. Values standing for several are written as labels
+    // the way exact does, and the proposal picks one of the original values uniformly for a
+    // variable and everything tied to it.
+    fn change(&mut self) {
+        let tied = self.tied;
+        let var = self.rng.below(self.assignment.len());
+        let total: usize = self.counts.iter().sum();
+        let mut pick = self.rng.below(total);
+        let mut value = 0;
+        while pick >= self.counts[value] {
+            pick -= self.counts[value];
+            value += 1;
+        }
+
+        self.try_set(&[&tied[var]], &[Some(value + self.k * pick)]);
+    }
+
+    // Single changes cannot move the one variable taking a value in ExactlyOne elsewhere, or
+    // trade values between variables that must differ, without passing through a broken state.
+    fn swap(&mut self) {
+        let tied = self.tied;
+        let first = &tied[self.rng.below(self.assignment.len())];
+        let second = &tied[self.rng.below(self.assignment.len())];
+        let (one, two) = (self.assignment[first[0]], self.assignment[second[0]]);
+        if one != two {
+            self.try_set(&[first, second], &[two, one]);
+        }
+    }
+
+    // How often each variable took each value over the kept sweeps, indexed by var * k + value.
+    fn run(&mut self, sweeps: usize) -> Vec<f64> {
+        let varc = self.assignment.len();
+        let mut frequency = vec![0.0; varc * self.k];
+        let burn_in = sweeps / 10;
+        for sweep in 0..(burn_in + sweeps) {
+            for _ in 0..varc {
+                if self.rng.below(2) == 0 {
+                    self.change();
+                } else {
+                    self.swap();
+                }
+            }
+            if sweep >= burn_in {
+                for (var, id) in self.assignment.iter().enumerate() {
+                    let value = id.expect("Every variable is assigned.") % self.k;
+                    frequency[var * self.k + value] += 1.0;
+                }
+            }
+        }
+        for count in frequency.iter_mut() {
+            *count /= sweeps as f64;
+        }
+        return frequency;
+    }
+}
+
+// The variables DoubleEq ties to each variable, through any chain of them.
+fn tied(optimizer: &EntropyOptimizer) -> Vec<Vec<usize>> {
+    let mut block: Vec<usize> = (0..optimizer.varc).collect();
+    for contra in optimizer.contras.iter() {
+        if let EntropyConstraint::DoubleEq(first, second) = *contra {
+            let (keep, merge) = (block[first], block[second]);
+            for entry in block.iter_mut().filter(|entry| **entry == merge) {
+                *entry = keep;
+            }
+        }
+    }
+    return (0..optimizer.varc)
+        .map(|var| (0..optimizer.varc).filter(|&other| block[other] == block[var]).collect())
+        .collect();
+}
+
+// The Gelman-Rubin statistic of one marginal, from how often each chain saw the value over its
+// sweeps. It nears one as the spread between chains comes down to what the spread within each
+// predicts. Chains that never saw the value change say nothing of whether they could, so that
+// is infinite too, and it is up to the caller to know when the value cannot change.
+fn r_hat(frequencies: &[f64], sweeps: usize) -> f64 {
+    let n = sweeps as f64;
+    let chains = frequencies.len() as f64;
+    let mean = frequencies.iter().sum::<f64>() / chains;
+    let between = frequencies.iter().map(|&frequency| (frequency - mean).powi(2)).sum::<f64>() / (chains - 1.0);
+    let within = frequencies.iter().map(|&frequency| frequency * (1.0 - frequency)).sum::<f64>() / chains;
+    if within == 0.0 {
+        return f64::INFINITY;
+    }
+    return (((n - 1.0) / n * within + between) / within).sqrt();
+}
+
+// Estimates the marginals when every assignment satisfying the constraints is equally likely,
+// by Metropolis sampling. Each chain starts from an assignment the exact search finds trying
+// values in its own random order, so that chains stuck near their start disagree.
+pub fn marginals(optimizer: &EntropyOptimizer, counts: &[f64], sweeps: usize) -> Result<Estimate, String> {
+    if sweeps == 0 {
+        return Err("Sampling needs at least one sweep.".to_string());
+    }
+    let watching = exact::watching(optimizer);
+    let tied = tied(optimizer);
+    let domains = try!(Domains::new(optimizer, counts));
+    let sizes: Vec<usize> = counts.iter().map(|&count| count as usize).collect();
+    let k = optimizer.k;
+
+    let chains: Vec<Vec<f64>> = try!((0..CHAINS).into_par_iter()
+        .map(|chain| {
+            let mut rng = Rng(SEED.wrapping_mul(chain as u64 + 1));
+            let order: Vec<Vec<usize>> = (0..optimizer.varc).map(|_| rng.shuffled(k)).collect();
+            let start = try!(exact::consistent_assignment(optimizer, counts, &order));
+            let mut chain = Chain{
+                k,
+                counts: &sizes,
+                watching: &watching,
+                tied: &tied,
+                assignment: start.into_iter().map(Some).collect(),
+                rng
+            };
+            Ok(chain.run(sweeps))
+        })
+        .collect::<Result<Vec<Vec<f64>>, String>>());
+
+    let mut marginals: HashMap<VarAndValue, f64> = HashMap::new();
+    let mut errors: HashMap<VarAndValue, f64> = HashMap::new();
+    let mut unconverged: BTreeSet<usize> = BTreeSet::new();
+    let n = CHAINS as f64;
+    for var in 0..optimizer.varc {
+        // Values the presolve rules out, or a variable it leaves one value, need no sampling.
+        let settled = (0..k).filter(|&value| domains.allows(var, value)).count() == 1;
+        for value in 0..k {
+            let index = var * k + value;
+            let frequencies: Vec<f64> = chains.iter().map(|chain| chain[index]).collect();
+            if !settled && domains.allows(var, value) && r_hat(&frequencies, sweeps) >= MAX_R_HAT {
+                unconverged.insert(var);
+            }
+            let mean = chains.iter().map(|chain| chain[index]).sum::<f64>() / n;
+            let variance = chains.iter().map(|chain| (chain[index] - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            let varval = VarAndValue{ var, value };
+            marginals.insert(varval, mean);
+            errors.insert(varval, (variance / n).sqrt());
+        }
+    }
+    return Ok(Estimate{ marginals, errors, unconverged });
+}
@@ -0,0 +1,167 @@
+use alchemy::{MULT, VariableType};
+use dual::{rows, Row};
+use gradient_descent::{Options, Solution, StopReason};
+use nalgebra::DVector;
+use std::f64;
+
+type DynVector = DVector<f64>;
+
+// Sweeps are far cheaper than Newton iterations but need many more of them.
+pub const MAX_SWEEPS: usize = 10000;
+const MAX_ROW_ITER: usize = 100;
+// No single Newton step on a multiplier scales cells by more than e^MAX_JUMP.
+const MAX_JUMP: f64 = 10.0;
+
+// Each row is solved on its own here, with the others held fixed.
+impl Row {
+    // Moving the multiplier by delta scales each cell by e^(-coefficient * delta / MULT).
+    // Returns the residual and its slope.
+    fn residual_after(&self, x: &DynVector, delta: f64) -> (f64, f64) {
+        let mut value = -self.target;
+        let mut slope = 0.0;
+        for &(i, coefficient) in self.cells.iter() {
+            let moved = x[i] * (-coefficient * delta / MULT).exp();
+            value += coefficient * moved;
+            slope -= coefficient * coefficient * moved / MULT;
+        }
+        return (value, slope);
+    }
+
+    // The multiplier change that meets the row, or for an inequality past its target, lets go of it.
+    fn solve(&self, x: &DynVector) -> f64 {
+        let delta = self.solve_unit(x).unwrap_or_else(|| self.search(x));
+        // An inequality already past its target lets go of it, which leaves its multiplier at zero.
+        if self.at_least {
+            return delta.min(-x[self.lagrangian]);
+        }
+        return delta;
+    }
+
+    // Every row the problems build has coefficients of one and minus one, and then the residual
+    // after delta is positive * u - negative / u - target for u = e^(-delta / MULT), a quadratic in u.
+    fn solve_unit(&self, x: &DynVector) -> Option<f64> {
+        let cells = x.as_slice();
+        let mut positive = 0.0;
+        let mut negative = 0.0;
+        for &(i, coefficient) in self.cells.iter() {
+            if coefficient == 1.0 {
+                positive += cells[i];
+            } else if coefficient == -1.0 {
+                negative += cells[i];
+            } else {
+                return None;
+            }
+        }
+        let target = self.target;
+        let u = (target + (target * target + 4.0 * positive * negative).sqrt()) / (2.0 * positive);
+        if !(u > 0.0 && u.is_finite()) {
+            return None;
+        }
+        return Some(-MULT * u.ln());
+    }
+
+    // The residual only falls as delta grows, so Newton is kept inside the bracket
+    // around the root found so far and bisects whenever it would leave it.
+    fn search(&self, x: &DynVector) -> f64 {
+        let mut delta = 0.0;
+        let mut low = f64::NEG_INFINITY;
+        let mut high = f64::INFINITY;
+        for _ in 0..MAX_ROW_ITER {
+            let (value, slope) = self.residual_after(x, delta);
+            if value == 0.0 {
+                break;
+            } else if value > 0.0 {
+                low = delta;
+            } else {
+                high = delta;
+            }
+
+            let mut next = delta - value / slope;
+            if !(next > low && next < high) {
+                next = if low.is_finite() && high.is_finite() {
+                    (low + high) / 2.0
+                } else if low.is_finite() {
+                    low + MAX_JUMP
+                } else {
+                    high - MAX_JUMP
+                };
+            }
+            next = next.max(delta - MAX_JUMP).min(delta + MAX_JUMP);
+            if (next - delta).abs() <= f64::EPSILON * (1.0 + delta.abs()) {
+                delta = next;
+                break;
+            }
+            delta = next;
+        }
+        return delta;
+    }
+
+    // An inequality is satisfied once it reaches its target with a multiplier of at most zero,
+    // and one of the two is exactly zero.
+    fn violation(&self, x: &DynVector) -> f64 {
+        let residual = self.residual(x);
+        return if self.at_least { residual.min(-x[self.lagrangian]) } else { residual };
+    }
+
+    fn apply(&self, x: &mut DynVector, delta: f64) {
+        let scale = (-delta / MULT).exp();
+        let cells = x.as_mut_slice();
+        for &(i, coefficient) in self.cells.iter() {
+            cells[i] *= if coefficient == 1.0 {
+                scale
+            } else if coefficient == -1.0 {
+                1.0 / scale
+            } else {
+                (-coefficient * delta / MULT).exp()
+            };
+        }
+        x[self.lagrangian] += delta;
+    }
+}
+
+// Dual coordinate ascent, which is iterative proportional fitting when every constraint
+// is a plain sum. Each constraint in turn gets the multiplier that satisfies it exactly
+// with the others held fixed. The cells are always the optimum for the current
+// multipliers, so only the constraints are left in the residual, and the cost of a sweep
+// grows with the number of cells rather than with a linear solve.
+pub fn optimize(var_meaning: &[VariableType], start: DynVector, options: &Options) -> Result<Solution, String> {
+    let rows = rows(var_meaning);
+    let residual = |x: &DynVector| -> f64 {
+        rows.iter().map(|row| row.violation(x).powi(2)).sum::<f64>().sqrt()
+    };
+
+    let mut x = start;
+    let initial = residual(&x);
+    let mut iterations = 0;
+    let mut small_step = false;
+    loop {
+        let current = residual(&x);
+        if !current.is_finite() {
+            return Err(format!("Scaling diverged after {} sweeps.", iterations));
+        }
+        let reason = if current <= options.absolute_tolerance {
+            Some(StopReason::AbsoluteResidual)
+        } else if current <= options.relative_tolerance * initial {
+            Some(StopReason::RelativeResidual)
+        } else if small_step {
+            Some(StopReason::SmallStep)
+        } else if iterations >= options.max_iter {
+            Some(StopReason::MaxIterations)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Ok(Solution{ x, iterations, residual: current, reason });
+        }
+
+        let mut largest: f64 = 0.0;
+        for row in rows.iter() {
+            let delta = row.solve(&x);
+            row.apply(&mut x, delta);
+            largest = largest.max(delta.abs());
+        }
+        iterations += 1;
+        // Cells change by about delta / MULT relative to themselves.
+        small_step = largest / MULT <= options.step_tolerance;
+    }
+}
@@ -0,0 +1,487 @@
+use alchemy::{EntropyOptimizer, Method, OptimizationResult};
+use solver::{EntropyConstraint, VarAndValue};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+
+// Every ingredient has exactly four effects, so each ingredient owns four variables.
+pub const SLOTS: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ingredient(pub usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MagicEffect(pub usize);
+
+// Slots are numbered 1 to 4 like in the game, but stored zero based.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EffectSlot(usize);
+
+impl EffectSlot {
+    pub fn new(number: usize) -> Option<EffectSlot> {
+        return if (1..=SLOTS).contains(&number) {
+            Some(EffectSlot(number - 1))
+        } else {
+            None
+        };
+    }
+
+    pub fn all() -> Vec<EffectSlot> {
+        return (0..SLOTS).map(EffectSlot).collect();
+    }
+
+    pub fn index(&self) -> usize {
+        return self.0;
+    }
+
+    pub fn number(&self) -> usize {
+        return self.0 + 1;
+    }
+}
+
+impl Display for EffectSlot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "slot {}", self.number())
+    }
+}
+
+pub struct Catalog {
+    ingredients: Vec<String>,
+    effects: Vec<String>,
+    ingredient_ids: HashMap<String, Ingredient>,
+    effect_ids: HashMap<String, MagicEffect>
+}
+
+impl Catalog {
+    pub fn new(ingredients: Vec<String>, effects: Vec<String>) -> Catalog {
+        let ingredient_ids = ingredients.iter().enumerate()
+            .map(|(i, name)| (name.clone(), Ingredient(i)))
+            .collect();
+        let effect_ids = effects.iter().enumerate()
+            .map(|(i, name)| (name.clone(), MagicEffect(i)))
+            .collect();
+        return Catalog{ ingredients, effects, ingredient_ids, effect_ids };
+    }
+
+    pub fn ingredient(&self, name: &str) -> Option<Ingredient> {
+        return self.ingredient_ids.get(name).cloned();
+    }
+
+    pub fn effect(&self, name: &str) -> Option<MagicEffect> {
+        return self.effect_ids.get(name).cloned();
+    }
+
+    pub fn ingredient_name(&self, ingredient: Ingredient) -> &str {
+        return &self.ingredients[ingredient.0];
+    }
+
+    pub fn effect_name(&self, effect: MagicEffect) -> &str {
+        return &self.effects[effect.0];
+    }
+
+    pub fn ingredients(&self) -> Vec<Ingredient> {
+        return (0..self.ingredients.len()).map(Ingredient).collect();
+    }
+
+    pub fn effects(&self) -> Vec<MagicEffect> {
+        return (0..self.effects.len()).map(MagicEffect).collect();
+    }
+
+    pub fn varc(&self) -> usize {
+        return self.ingredients.len() * SLOTS;
+    }
+
+    pub fn k(&self) -> usize {
+        return self.effects.len();
+    }
+
+    pub fn variable(&self, ingredient: Ingredient, slot: EffectSlot) -> usize {
+        return ingredient.0 * SLOTS + slot.index();
+    }
+
+    pub fn slot_of(&self, var: usize) -> (Ingredient, EffectSlot) {
+        return (Ingredient(var / SLOTS), EffectSlot(var % SLOTS));
+    }
+
+    pub fn var_and_value(&self, ingredient: Ingredient, slot: EffectSlot, effect: MagicEffect) -> VarAndValue {
+        return VarAndValue{ var: self.variable(ingredient, slot), value: effect.0 };
+    }
+}
+
+// What came out of the alchemy table after mixing some ingredients. No effects means the mix failed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Experiment {
+    pub ingredients: Vec<Ingredient>,
+    pub effects: BTreeSet<MagicEffect>
+}
+
+impl Experiment {
+    pub fn new(first: Ingredient, second: Ingredient, effects: Vec<MagicEffect>) -> Experiment {
+        return Experiment{ ingredients: vec![first, second], effects: effects.into_iter().collect() };
+    }
+
+    pub fn triple(first: Ingredient, second: Ingredient, third: Ingredient, effects: Vec<MagicEffect>) -> Experiment {
+        return Experiment{ ingredients: vec![first, second, third], effects: effects.into_iter().collect() };
+    }
+
+    pub fn failed(first: Ingredient, second: Ingredient) -> Experiment {
+        return Experiment::new(first, second, Vec::new());
+    }
+
+    pub fn succeeded(&self) -> bool {
+        return !self.effects.is_empty();
+    }
+
+    // The potion has every effect shared by at least two of the ingredients, and nothing else.
+    pub fn constraints(&self, catalog: &Catalog) -> Vec<EntropyConstraint> {
+        let mut result: Vec<EntropyConstraint> = Vec::new();
+        for (i, &first) in self.ingredients.iter().enumerate() {
+            for &second in self.ingredients[(i + 1)..].iter() {
+                self.pair_constraints(catalog, first, second, &mut result);
+            }
+        }
+
+        let slots = |ingredient: Ingredient| -> Vec<usize> {
+            EffectSlot::all().into_iter()
+                .map(|slot| catalog.variable(ingredient, slot))
+                .collect()
+        };
+        for &effect in self.effects.iter() {
+            if self.ingredients.len() == 2 {
+                // Every effect the potion has is in exactly one slot of each ingredient.
+                for &ingredient in self.ingredients.iter() {
+                    result.push(EntropyConstraint::ExactlyOne(slots(ingredient), effect.0));
+                }
+            } else {
+                let groups = self.ingredients.iter().map(|&ingredient| slots(ingredient)).collect();
+                result.push(EntropyConstraint::AtLeast(groups, 2, effect.0));
+            }
+        }
+
+        return result;
+    }
+
+    fn pair_constraints(&self, catalog: &Catalog, first: Ingredient, second: Ingredient, result: &mut Vec<EntropyConstraint>) {
+        for slot1 in EffectSlot::all() {
+            for slot2 in EffectSlot::all() {
+                let var1 = catalog.variable(first, slot1);
+                let var2 = catalog.variable(second, slot2);
+                if !self.succeeded() {
+                    result.push(EntropyConstraint::DoubleNeq(var1, var2));
+                    continue;
+                }
+
+                // Any effect the potion lacks is not shared between the two ingredients.
+                for effect in catalog.effects() {
+                    if !self.effects.contains(&effect) {
+                        result.push(EntropyConstraint::JointNeq(
+                            VarAndValue{ var: var1, value: effect.0 },
+                            VarAndValue{ var: var2, value: effect.0 }));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Knowledge<'a> {
+    catalog: &'a Catalog,
+    contras: HashSet<EntropyConstraint>,
+    experiments: Vec<Experiment>
+}
+
+impl<'a> Knowledge<'a> {
+    pub fn new(catalog: &'a Catalog) -> Knowledge<'a> {
+        let mut contras: HashSet<EntropyConstraint> = HashSet::new();
+        // An ingredient never lists the same effect twice.
+        for ingredient in catalog.ingredients() {
+            for s1 in 0..SLOTS {
+                for s2 in (s1 + 1)..SLOTS {
+                    let var1 = catalog.variable(ingredient, EffectSlot(s1));
+                    let var2 = catalog.variable(ingredient, EffectSlot(s2));
+                    contras.insert(EntropyConstraint::DoubleNeq(var1, var2));
+                }
+            }
+        }
+
+        return Knowledge{ catalog, contras, experiments: Vec::new() };
+    }
+
+    pub fn catalog(&self) -> &'a Catalog {
+        return self.catalog;
+    }
+
+    pub fn constraints(&self) -> &HashSet<EntropyConstraint> {
+        return &self.contras;
+    }
+
+    pub fn rule_out(&mut self, ingredient: Ingredient, slot: EffectSlot, effect: MagicEffect) {
+        let varval = self.catalog.var_and_value(ingredient, slot, effect);
+        self.contras.insert(EntropyConstraint::SingleNeq(varval));
+    }
+
+    // Eating an ingredient or reading it in the inventory reveals the effect in a slot.
+    pub fn learn(&mut self, ingredient: Ingredient, slot: EffectSlot, effect: MagicEffect) {
+        let varval = self.catalog.var_and_value(ingredient, slot, effect);
+        self.contras.insert(EntropyConstraint::SingleEq(varval));
+    }
+
+    // Two ingredients that never share an effect produce nothing when mixed.
+    pub fn no_shared_effects(&mut self, first: Ingredient, second: Ingredient) {
+        self.record(Experiment::failed(first, second));
+    }
+
+    pub fn record(&mut self, experiment: Experiment) {
+        for contra in experiment.constraints(self.catalog) {
+            self.contras.insert(contra);
+        }
+        self.experiments.push(experiment);
+    }
+
+    pub fn experiments(&self) -> &[Experiment] {
+        return &self.experiments;
+    }
+
+    pub fn optimizer(&self) -> EntropyOptimizer {
+        return EntropyOptimizer{
+            varc: self.catalog.varc(),
+            k: self.catalog.k(),
+            contras: self.contras.clone()
+        };
+    }
+
+    pub fn solve(&self) -> Result<Beliefs<'a>, String> {
+        let result = try!(self.optimizer().optimize());
+        return Ok(Beliefs{ catalog: self.catalog, result });
+    }
+
+    // After recording more, solves only the parts of the problem the new constraints touch.
+    pub fn solve_from(&self, previous: &Beliefs<'a>) -> Result<Beliefs<'a>, String> {
+        let result = try!(self.optimizer().optimize_from(&previous.result));
+        return Ok(Beliefs{ catalog: self.catalog, result });
+    }
+
+    pub fn solve_with(&self, method: Method) -> Result<Beliefs<'a>, String> {
+        let result = try!(self.optimizer().optimize_with(method, &method.default_options()));
+        return Ok(Beliefs{ catalog: self.catalog, result });
+    }
+}
+
+pub struct Beliefs<'a> {
+    catalog: &'a Catalog,
+    result: OptimizationResult
+}
+
+impl<'a> Beliefs<'a> {
+    pub fn prob(&self, ingredient: Ingredient, slot: EffectSlot, effect: MagicEffect) -> f64 {
+        return self.result.var_prob(self.catalog.variable(ingredient, slot), effect.0);
+    }
+
+    // Slots are mutually exclusive, so the chance of having the effect at all is the sum over slots.
+    pub fn has_effect(&self, ingredient: Ingredient, effect: MagicEffect) -> f64 {
+        return EffectSlot::all().into_iter()
+            .map(|slot| self.prob(ingredient, slot, effect))
+            .sum();
+    }
+
+    // The chance both ingredients have the effect, summed over pairs of slots since each has it in
+    // at most one. Slots whose joint was solved use it, and the rest are taken as independent.
+    pub fn shares_effect(&self, first: Ingredient, second: Ingredient, effect: MagicEffect) -> f64 {
+        let mut result = 0.0;
+        for slot1 in EffectSlot::all() {
+            for slot2 in EffectSlot::all() {
+                let var1 = self.catalog.variable(first, slot1);
+                let var2 = self.catalog.variable(second, slot2);
+                result += self.result.joint_prob(var1, effect.0, var2, effect.0)
+                    .unwrap_or_else(|| self.prob(first, slot1, effect) * self.prob(second, slot2, effect));
+            }
+        }
+        return result;
+    }
+
+    // The effects the slot can still hold. Exact beliefs give zero to every effect no consistent
+    // assignment has, while maximum entropy only gives zero to those ruled out directly.
+    pub fn possible_effects(&self, ingredient: Ingredient, slot: EffectSlot) -> Vec<MagicEffect> {
+        return self.catalog.effects().into_iter()
+            .filter(|&effect| self.prob(ingredient, slot, effect) > 0.0)
+            .collect();
+    }
+
+    pub fn entropy(&self) -> f64 {
+        return self.result.entropy();
+    }
+
+    pub fn result(&self) -> &OptimizationResult {
+        return &self.result;
+    }
+}
+
+impl<'a> Display for Beliefs<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        try!(write!(f, "{{\n"));
+        for ingredient in self.catalog.ingredients() {
+            for slot in EffectSlot::all() {
+                for effect in self.catalog.effects() {
+                    let prob = self.prob(ingredient, slot, effect);
+                    if prob > 0.0 {
+                        try!(write!(f, "\tPr[{} {} = {}] = {}\n",
+                            self.catalog.ingredient_name(ingredient), slot,
+                            self.catalog.effect_name(effect), prob));
+                    }
+                }
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database;
+
+    fn catalog() -> Catalog {
+        return database::catalog(&["Wheat", "Blue Mountain Flower", "Salt Pile", "Blisterwort"]).unwrap();
+    }
+
+    // What the game would show for the mix, from the bundled effects.
+    fn mix(catalog: &Catalog, truth: &[MagicEffect], ingredients: &[Ingredient]) -> Experiment {
+        let effects = catalog.effects().into_iter()
+            .filter(|&effect| {
+                ingredients.iter()
+                    .filter(|&&ingredient| EffectSlot::all().into_iter().any(|slot| truth[catalog.variable(ingredient, slot)] == effect))
+                    .count() >= 2
+            })
+            .collect();
+        return Experiment{ ingredients: ingredients.to_vec(), effects };
+    }
+
+    #[test]
+    fn slots_are_numbered_like_the_game() {
+        assert_eq!(EffectSlot::new(0), None);
+        assert_eq!(EffectSlot::new(SLOTS + 1), None);
+        let slot = EffectSlot::new(3).unwrap();
+        assert_eq!((slot.index(), slot.number()), (2, 3));
+
+        let catalog = catalog();
+        let salt = catalog.ingredient("Salt Pile").unwrap();
+        let var = catalog.variable(salt, slot);
+        assert_eq!(var, 10);
+        assert_eq!(catalog.slot_of(var), (salt, slot));
+    }
+
+    #[test]
+    fn ground_truth_satisfies_every_experiment() {
+        let catalog = catalog();
+        let truth = database::ground_truth(&catalog).unwrap();
+        let wheat = catalog.ingredient("Wheat").unwrap();
+        let flower = catalog.ingredient("Blue Mountain Flower").unwrap();
+        assert_eq!(catalog.effect_name(truth[catalog.variable(wheat, EffectSlot::new(1).unwrap())]), "Restore Health");
+
+        let mut knowledge = Knowledge::new(&catalog);
+        let ingredients = catalog.ingredients();
+        for (i, &first) in ingredients.iter().enumerate() {
+            for (j, &second) in ingredients.iter().enumerate().skip(i + 1) {
+                knowledge.record(mix(&catalog, &truth, &[first, second]));
+                for &third in ingredients[(j + 1)..].iter() {
+                    knowledge.record(mix(&catalog, &truth, &[first, second, third]));
+                }
+            }
+        }
+        let shared: Vec<&str> = mix(&catalog, &truth, &[wheat, flower]).effects.into_iter()
+            .map(|effect| catalog.effect_name(effect))
+            .collect();
+        assert_eq!(shared, vec!["Fortify Health", "Restore Health"]);
+
+        let assignment: Vec<Option<usize>> = truth.iter().map(|effect| Some(effect.0)).collect();
+        assert!(knowledge.constraints().iter().all(|contra| contra.satisfied(&assignment)));
+        assert_eq!(knowledge.experiments().len(), 10);
+    }
+
+    #[test]
+    fn nothing_known_is_uniform() {
+        let catalog = catalog();
+        let beliefs = Knowledge::new(&catalog).solve().unwrap();
+        let k = catalog.k() as f64;
+        let wheat = catalog.ingredient("Wheat").unwrap();
+        for effect in catalog.effects() {
+            assert!((beliefs.has_effect(wheat, effect) - SLOTS as f64 / k).abs() < 1e-9);
+        }
+        assert!(beliefs.result().converged());
+    }
+
+    #[test]
+    fn failed_mix_lowers_shared_effects() {
+        let catalog = catalog();
+        let wheat = catalog.ingredient("Wheat").unwrap();
+        let salt = catalog.ingredient("Salt Pile").unwrap();
+        let mut knowledge = Knowledge::new(&catalog);
+        knowledge.no_shared_effects(wheat, salt);
+        let beliefs = knowledge.solve().unwrap();
+        assert!(beliefs.result().converged());
+
+        // Either ingredient may have any effect, but not both the same one.
+        let effect = catalog.effect("Slow").unwrap();
+        let alone = SLOTS as f64 / catalog.k() as f64;
+        let together: f64 = EffectSlot::all().into_iter()
+            .flat_map(|slot1| EffectSlot::all().into_iter().map(move |slot2| (slot1, slot2)))
+            .map(|(slot1, slot2)| beliefs.result().joint_prob(
+                catalog.variable(wheat, slot1), effect.0, catalog.variable(salt, slot2), effect.0).unwrap())
+            .sum();
+        assert!(together.abs() < 1e-9);
+        assert!((beliefs.has_effect(wheat, effect) - beliefs.has_effect(salt, effect)).abs() < 1e-9);
+        assert!(beliefs.has_effect(wheat, effect) < alone);
+    }
+
+    #[test]
+    fn one_successful_mix_converges() {
+        let catalog = catalog();
+        let truth = database::ground_truth(&catalog).unwrap();
+        let wheat = catalog.ingredient("Wheat").unwrap();
+        let flower = catalog.ingredient("Blue Mountain Flower").unwrap();
+        let mut knowledge = Knowledge::new(&catalog);
+        knowledge.record(mix(&catalog, &truth, &[wheat, flower]));
+        let beliefs = knowledge.solve().unwrap();
+        assert!(beliefs.result().converged());
+        for name in ["Restore Health", "Fortify Health"].iter() {
+            let effect = catalog.effect(name).unwrap();
+            assert!((beliefs.has_effect(wheat, effect) - 1.0).abs() < 1e-9);
+            assert!((beliefs.has_effect(flower, effect) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn scaling_agrees_with_newton() {
+        let catalog = catalog();
+        let truth = database::ground_truth(&catalog).unwrap();
+        let wheat = catalog.ingredient("Wheat").unwrap();
+        let flower = catalog.ingredient("Blue Mountain Flower").unwrap();
+        let mut knowledge = Knowledge::new(&catalog);
+        knowledge.record(mix(&catalog, &truth, &[wheat, flower]));
+        let newton = knowledge.solve_with(Method::Newton).unwrap();
+        let scaling = knowledge.solve_with(Method::Scaling).unwrap();
+        assert!(scaling.result().converged());
+        for ingredient in catalog.ingredients() {
+            for slot in EffectSlot::all() {
+                for effect in catalog.effects() {
+                    assert!((newton.prob(ingredient, slot, effect) - scaling.prob(ingredient, slot, effect)).abs() < 1e-8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn learned_effect_is_ruled_out_of_the_other_slots() {
+        let catalog = catalog();
+        let wheat = catalog.ingredient("Wheat").unwrap();
+        let effect = catalog.effect("Restore Health").unwrap();
+        let mut knowledge = Knowledge::new(&catalog);
+        knowledge.learn(wheat, EffectSlot::new(1).unwrap(), effect);
+        let beliefs = knowledge.solve().unwrap();
+        assert!(beliefs.result().converged());
+        for slot in EffectSlot::all().into_iter().skip(1) {
+            assert_eq!(beliefs.prob(wheat, slot, effect), 0.0);
+        }
+    }
+}
@@ -1,6 +1,9 @@
-use std::collections::{HashSet, HashMap};
+use alchemy::EntropyOptimizer;
+use joint::JointTable;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct VarAndValue {
     pub var: usize,
     pub value: usize
@@ -11,7 +14,7 @@ pub struct VarValIter {
     end: VarAndValue
 }
 
-pub impl Iterator for VarValIter {
+impl Iterator for VarValIter {
     type Item = VarAndValue;
 
     fn next(&mut self) -> Option<VarAndValue> {
@@ -30,7 +33,7 @@ pub impl Iterator for VarValIter {
     }
 }
 
-pub impl VarAndValue {
+impl VarAndValue {
     // varEnd is non-inclusive.
     pub fn enumerate(var_start: usize, var_end: usize, ksize: usize) -> VarValIter {
         let current = VarAndValue{var: var_start, value: 0};
@@ -43,90 +46,182 @@ pub impl VarAndValue {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum EntropyConstraint {
     DoubleNeq(usize, usize),
-    SingleNeq(VarAndValue)
+    SingleNeq(VarAndValue),
+    JointNeq(VarAndValue, VarAndValue), // The two variables never take these two values together.
+    SingleEq(VarAndValue),
+    DoubleEq(usize, usize),
+    ExactlyOne(Vec<usize>, usize), // Exactly one of the variables takes the value.
+    AtLeast(Vec<Vec<usize>>, usize, usize) // At least this many groups have a variable taking the value, at most one per group.
 }
 
 
-use self::EntropyConstraint{DoubleNeq, SingleNeq};
+use self::EntropyConstraint::{DoubleNeq, SingleNeq, JointNeq, SingleEq, DoubleEq, ExactlyOne, AtLeast};
 
-pub struct EntropyOptimizer {
-    pub varc: usize,
-    pub k: usize,
-    pub contras: Vec<EntropyConstraint>
+impl EntropyConstraint {
+    // Every variable the constraint talks about.
+    pub fn variables(&self) -> Vec<usize> {
+        return match *self {
+            DoubleNeq(first, second) | DoubleEq(first, second) => vec![first, second],
+            SingleNeq(varval) | SingleEq(varval) => vec![varval.var],
+            JointNeq(first, second) => vec![first.var, second.var],
+            ExactlyOne(ref vars, _) => vars.clone(),
+            AtLeast(ref groups, _, _) => groups.iter().flat_map(|group| group.iter().cloned()).collect()
+        };
+    }
+
+    // Every value the constraint talks about. DoubleNeq and DoubleEq treat all values alike.
+    pub fn values(&self) -> Vec<usize> {
+        return match *self {
+            DoubleNeq(_, _) | DoubleEq(_, _) => Vec::new(),
+            SingleNeq(varval) | SingleEq(varval) => vec![varval.value],
+            JointNeq(first, second) => vec![first.value, second.value],
+            ExactlyOne(_, value) | AtLeast(_, _, value) => vec![value]
+        };
+    }
+
+    // Whether the constraint fits a problem with varc variables and k values. Everything that
+    // looks at a constraint assumes it names at least one variable.
+    pub fn check(&self, varc: usize, k: usize) -> Result<(), String> {
+        if let Some(var) = self.variables().into_iter().find(|&var| var >= varc) {
+            return Err(format!("{:?} names variable {} of only {}.", self, var, varc));
+        }
+        if let Some(value) = self.values().into_iter().find(|&value| value >= k) {
+            return Err(format!("{:?} names value {} of only {}.", self, value, k));
+        }
+        let repeats = |vars: &[usize]| vars.iter().enumerate().any(|(i, var)| vars[(i + 1)..].contains(var));
+        return match *self {
+            DoubleNeq(first, second) | DoubleEq(first, second) if first == second => {
+                Err(format!("{:?} names variable {} twice.", self, first))
+            },
+            JointNeq(first, second) if first.var == second.var => {
+                Err(format!("{:?} names variable {} twice.", self, first.var))
+            },
+            ExactlyOne(ref vars, _) if repeats(vars) => Err(format!("{:?} names a variable twice.", self)),
+            AtLeast(ref groups, _, _) if groups.iter().any(|group| repeats(group)) => {
+                Err(format!("{:?} names a variable twice in a group.", self))
+            },
+            ExactlyOne(ref vars, _) if vars.is_empty() => Err(format!("{:?} has no variables.", self)),
+            AtLeast(ref groups, _, _) if groups.is_empty() || groups.iter().any(|group| group.is_empty()) => {
+                Err(format!("{:?} has an empty group or none at all.", self))
+            },
+            AtLeast(ref groups, count, _) if count > groups.len() => {
+                Err(format!("{:?} needs {} of only {} groups.", self, count, groups.len()))
+            },
+            _ => Ok(())
+        };
+    }
+
+    // Whether the assignment can still satisfy the constraint, with None for unassigned variables.
+    // Once every variable it talks about is assigned, this is whether it holds.
+    pub fn satisfied(&self, assignment: &[Option<usize>]) -> bool {
+        let takes = |var: usize, value: usize| assignment[var] == Some(value);
+        return match *self {
+            DoubleNeq(first, second) => match (assignment[first], assignment[second]) {
+                (Some(one), Some(two)) => one != two,
+                _ => true
+            },
+            DoubleEq(first, second) => match (assignment[first], assignment[second]) {
+                (Some(one), Some(two)) => one == two,
+                _ => true
+            },
+            SingleNeq(varval) => !takes(varval.var, varval.value),
+            SingleEq(varval) => assignment[varval.var].is_none_or(|value| value == varval.value),
+            JointNeq(first, second) => !(takes(first.var, first.value) && takes(second.var, second.value)),
+            ExactlyOne(ref vars, value) => {
+                let taking = vars.iter().filter(|&&var| takes(var, value)).count();
+                let open = vars.iter().filter(|&&var| assignment[var].is_none()).count();
+                taking <= 1 && taking + open >= 1
+            },
+            AtLeast(ref groups, count, value) => {
+                let mut possible = 0;
+                for group in groups.iter() {
+                    let taking = group.iter().filter(|&&var| takes(var, value)).count();
+                    if taking > 1 {
+                        return false;
+                    }
+                    if taking == 1 || group.iter().any(|&var| assignment[var].is_none()) {
+                        possible += 1;
+                    }
+                }
+                possible >= count
+            }
+        };
+    }
+
+    pub fn remap<V: Fn(usize) -> usize, K: Fn(usize) -> usize>(&self, var: V, value: K) -> EntropyConstraint {
+        let varval = |old: VarAndValue| VarAndValue{ var: var(old.var), value: value(old.value) };
+        return match *self {
+            DoubleNeq(first, second) => DoubleNeq(var(first), var(second)),
+            DoubleEq(first, second) => DoubleEq(var(first), var(second)),
+            SingleNeq(old) => SingleNeq(varval(old)),
+            SingleEq(old) => SingleEq(varval(old)),
+            JointNeq(first, second) => JointNeq(varval(first), varval(second)),
+            ExactlyOne(ref vars, old) => ExactlyOne(vars.iter().map(|&old| var(old)).collect(), value(old)),
+            AtLeast(ref groups, count, old) => AtLeast(
+                groups.iter().map(|group| group.iter().map(|&old| var(old)).collect()).collect(),
+                count,
+                value(old))
+        };
+    }
 }
 
 impl EntropyOptimizer {
+    // Splits the variables into groups that no constraint links, each of which can be solved alone.
+    // Values no constraint in a group mentions are interchangeable there, so they become one value.
     pub fn separate(&self) -> Vec<EntropySubProblem> {
-        let mut vars: HashSet<usize> = HashSet::new();
-        for var in 0..self.varc {
-            vars.insert(var);
-        }
-
+        let mut vars: BTreeSet<usize> = (0..self.varc).collect();
         let mut result: Vec<EntropySubProblem> = Vec::new();
         while !vars.is_empty() {
+            let start = *vars.iter().next().unwrap();
             let mut var_map: HashMap<usize, usize> = HashMap::new();
-            var_map.insert(vars.iter().next().unwrap(), 0);
+            var_map.insert(start, 0);
 
             let mut need_update = true;
             while need_update {
                 need_update = false;
-                for &contra in self.contras.iter() {
-                    if DoubleNeq(first, second) = contra {
-                        if var_map.get(&first).is_some() != var_map.get(&second).is_some() {
-                            need_update = true;
-                            var_map.entry(&first).or_insert(var_map.len());
-                            var_map.entry(&second).or_insert(var_map.len());
+                for contra in self.contras.iter() {
+                    let linked = contra.variables();
+                    let found = linked.iter().filter(|var| var_map.contains_key(var)).count();
+                    if found > 0 && found < linked.len() {
+                        need_update = true;
+                        for var in linked {
+                            let next = var_map.len();
+                            var_map.entry(var).or_insert(next);
                         }
                     }
                 }
             }
 
-            let mut new_contras: Vec<EntropyConstraint> = Vec::new();
-            let mut val_map: HashMap<usize, usize> = HashMap::new();
-            for &contra in self.contras.iter() {
-                match contra {
-                    DoubleNeq(first, second) => {
-                        let new_first = var_map.get(&first);
-                        let new_second = var_map.get(&second);
-                        if has_first.is_some() {
-                            new_contras.push(DoubleNeq(new_first.unwrap(), new_second.unwrap()));
-                        }
-                    },
-                    SingleNeq(VarAndValue{var, value}) => {
-                        let has_var = var_map.get(&var);
-                        if has_var.is_some() {
-                            let new_value = val_map.entry(&value).or_insert(val_map.len());
-                            new_contras.push(SingleNeq(VarAndValue{var: has_var.unwrap(), value: new_value}));
-                        }
-                    }
-                }
+            let kept: Vec<&EntropyConstraint> = self.contras.iter()
+                .filter(|contra| var_map.contains_key(&contra.variables()[0]))
+                .collect();
+            let mentioned: BTreeSet<usize> = kept.iter().flat_map(|contra| contra.values()).collect();
+            let val_map: HashMap<usize, usize> = mentioned.iter().enumerate()
+                .map(|(new_val, &old_val)| (old_val, new_val))
+                .collect();
+            let mut k_map: Vec<Vec<usize>> = mentioned.iter().map(|&value| vec![value]).collect();
+            let not_mentioned: Vec<usize> = (0..self.k).filter(|value| !mentioned.contains(value)).collect();
+            if !not_mentioned.is_empty() {
+                k_map.push(not_mentioned);
             }
 
-            for &to_remove in var_map.keys() {
-                vars.remove(&to_remove);
-            }
-
-            let not_mentioned: Vec<usize> = (0..self.k).filter(|k| val_map.get(&k).is_none()).collect();
-            let mut val_arr: Vec<Vec<usize>> = vec![Vec::new(); val_map.len()];
-
-            for &(old_val, new_val) in val_map.iter() {
-                val_arr[new_val].push(old_val);
-            }
-
-            val_arr.push(not_mentioned);
+            let contras = kept.iter()
+                .map(|contra| contra.remap(|var| var_map[&var], |value| val_map[&value]))
+                .collect();
 
             let mut var_arr: Vec<usize> = vec![0; var_map.len()];
-            for &(old_val, new_val) in var_map.iter() {
-                var_arr[new_val] = old_val;
+            for (&old_var, &new_var) in var_map.iter() {
+                var_arr[new_var] = old_var;
+                vars.remove(&old_var);
             }
 
             result.push(EntropySubProblem{
-                varMap: var_arr,
-                kMap: val_arr,
-                contras: new_contras
+                var_map: var_arr,
+                optimizer: EntropyOptimizer{ varc: var_map.len(), k: k_map.len(), contras },
+                k_map
             });
         }
 
@@ -134,38 +229,142 @@ impl EntropyOptimizer {
     }
 }
 
+// Subproblems share no variables, so their entropies add up to the entropy of the whole problem.
 pub struct EntropySubProblem {
-    pub varcMap: Vec<usize>, // varc of problem is varcMap.length() and each element is a map back to original variable.
-    pub kMap: Vec<Vec<usize>>, // Each element is the k values from original problem.
-    pub contras: Vec<EntropyConstraint>
+    pub var_map: Vec<usize>, // Each variable of the subproblem maps back to this original variable.
+    pub k_map: Vec<Vec<usize>>, // Each value of the subproblem stands for these original values.
+    pub optimizer: EntropyOptimizer
 }
 
-// Add together entropy from all subproblems for total entropy.
 impl EntropySubProblem {
-    pub fn varc(&self) -> usize {
-        return varcMap.len();
+    // Whether the previous problem had exactly this component, with the same constraints on its variables.
+    pub fn unchanged_since(&self, previous: &EntropyOptimizer) -> bool {
+        let vars: HashSet<usize> = self.var_map.iter().cloned().collect();
+        let now: HashSet<EntropyConstraint> = self.optimizer.contras.iter()
+            .map(|contra| contra.remap(|var| self.var_map[var], |value| self.k_map[value][0]))
+            .collect();
+        let before: HashSet<EntropyConstraint> = previous.contras.iter()
+            .filter(|contra| contra.variables().iter().any(|var| vars.contains(var)))
+            .cloned()
+            .collect();
+        return now == before;
     }
 
-    pub fn k(&self) -> usize {
-        return kMap.len();
+    // How many original values each value of the subproblem stands for.
+    pub fn counts(&self) -> Vec<f64> {
+        return self.k_map.iter().map(|values| values.len() as f64).collect();
     }
 
-    pub fn kcount(&self, kval: usize) -> usize {
-        return kMap.get(kval).unwrap().len();
+    // Writes the subproblem's marginals back in terms of the original variables and values.
+    pub fn expand(&self, marginals: &HashMap<VarAndValue, f64>, distribution: &mut HashMap<VarAndValue, f64>) {
+        for (varval, &prob) in marginals.iter() {
+            let values = &self.k_map[varval.value];
+            for &value in values.iter() {
+                distribution.insert(VarAndValue{ var: self.var_map[varval.var], value }, prob / values.len() as f64);
+            }
+        }
+    }
+
+    // Like expand, for joints. Two variables a constraint says differ can only take different values
+    // from a lumped value, and two it says are equal only the same one.
+    pub fn expand_joints(&self, joints: &HashMap<(usize, usize), JointTable>, k: usize, result: &mut HashMap<(usize, usize), JointTable>) {
+        for (&(first, second), joint) in joints.iter() {
+            let linked = |test1: usize, test2: usize| {
+                (test1 == first && test2 == second) || (test1 == second && test2 == first)
+            };
+            let same = !self.optimizer.contras.iter().any(|contra| match *contra {
+                DoubleNeq(test1, test2) => linked(test1, test2),
+                _ => false
+            });
+            let different = !self.optimizer.contras.iter().any(|contra| match *contra {
+                DoubleEq(test1, test2) => linked(test1, test2),
+                _ => false
+            });
+            let (var1, var2) = (self.var_map[first], self.var_map[second]);
+            let expanded = joint.expand(&self.k_map, k, same, different, var1 > var2);
+            result.insert((cmp::min(var1, var2), cmp::max(var1, var2)), expanded);
+        }
     }
 
-    pub fn entropy<Solution: ProblemSolution>(&self, solution: &Solution) -> f64 {
-        let mut result: f64 = 0.0;
-        for var1 in VarAndValue::enumerate(0, self.varc(), self.k()) {
-            let count1 = self.kcount(var.value);
-            for var2 in VarAndValue::enumerate(var1.var+1, self.varc(), self.k()) {
-                // full_count is the number of events represented uniformly under the given probability.
-                let full_count = count1 * self.kcount(var2.value);
-                let prob = solution.prob(var1, var2) / full_count;
-                result += -prob * (prob / full_count).log2(); // Divide by full_count since uniformly distributed over multiple events.
+    // Like expand, for marginals keyed by the variable they were read alongside.
+    pub fn expand_joint_marginals(&self, marginals: &HashMap<(usize, VarAndValue), f64>, result: &mut HashMap<(usize, VarAndValue), f64>) {
+        for (&(other, varval), &prob) in marginals.iter() {
+            let values = &self.k_map[varval.value];
+            for &value in values.iter() {
+                let key = (self.var_map[other], VarAndValue{ var: self.var_map[varval.var], value });
+                result.insert(key, prob / values.len() as f64);
             }
         }
+    }
+}
 
-        return result;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varval(var: usize, value: usize) -> VarAndValue {
+        return VarAndValue{ var, value };
+    }
+
+    fn problem(varc: usize, k: usize, contras: Vec<EntropyConstraint>) -> EntropyOptimizer {
+        return EntropyOptimizer{ varc, k, contras: contras.into_iter().collect() };
+    }
+
+    #[test]
+    fn separate_splits_unlinked_variables() {
+        let optimizer = problem(5, 3, vec![DoubleNeq(0, 1), DoubleNeq(3, 2)]);
+        let subs = optimizer.separate();
+        let vars: Vec<Vec<usize>> = subs.iter().map(|sub| sub.var_map.clone()).collect();
+        assert_eq!(vars, vec![vec![0, 1], vec![2, 3], vec![4]]);
+        assert!(subs[1].optimizer.contras.contains(&DoubleNeq(1, 0)));
+        assert!(subs[2].optimizer.contras.is_empty());
+    }
+
+    #[test]
+    fn separate_follows_chains_of_constraints() {
+        let optimizer = problem(4, 2, vec![DoubleNeq(2, 3), DoubleEq(0, 1), DoubleNeq(1, 2)]);
+        let subs = optimizer.separate();
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].optimizer.contras.len(), 3);
+    }
+
+    #[test]
+    fn separate_lumps_unmentioned_values() {
+        let optimizer = problem(2, 4, vec![DoubleNeq(0, 1), SingleNeq(varval(0, 2))]);
+        let subs = optimizer.separate();
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].k_map, vec![vec![2], vec![0, 1, 3]]);
+        assert_eq!(subs[0].counts(), vec![1.0, 3.0]);
+        assert!(subs[0].optimizer.contras.contains(&SingleNeq(varval(0, 0))));
+
+        let alone = problem(1, 3, Vec::new()).separate();
+        assert_eq!(alone[0].k_map, vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn expand_spreads_lumped_values_evenly() {
+        let optimizer = problem(3, 4, vec![SingleNeq(varval(2, 1))]);
+        let sub = optimizer.separate().pop().unwrap();
+        assert_eq!(sub.var_map, vec![2]);
+
+        let mut marginals: HashMap<VarAndValue, f64> = HashMap::new();
+        marginals.insert(varval(0, 0), 0.0);
+        marginals.insert(varval(0, 1), 1.0);
+        let mut distribution: HashMap<VarAndValue, f64> = HashMap::new();
+        sub.expand(&marginals, &mut distribution);
+        assert_eq!(distribution[&varval(2, 1)], 0.0);
+        for &value in [0, 2, 3].iter() {
+            assert!((distribution[&varval(2, value)] - 1.0 / 3.0).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn unchanged_since_compares_only_the_component() {
+        let before = problem(4, 3, vec![DoubleNeq(0, 1), DoubleNeq(2, 3)]);
+        let mut after = before.clone();
+        after.contras.insert(SingleNeq(varval(3, 1)));
+        let subs = after.separate();
+        assert!(subs[0].unchanged_since(&before));
+        assert!(!subs[1].unchanged_since(&before));
     }
 }